@@ -0,0 +1,121 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{GenericArgument, Pat, PatType, PathArguments, ReturnType, Type};
+
+fn is(ty: &Type, name: &str) -> bool {
+    matches!(ty, Type::Path(path) if path.path.is_ident(name))
+}
+
+fn is_string(ty: &Type) -> bool {
+    match ty {
+        Type::Slice(slice) => is(&slice.elem, "u8"),
+        ty => is(ty, "str"),
+    }
+}
+
+// Returns the statement that reads argument `narg` into `name`, and the
+// expression that passes it to the method. Borrowed strings are read straight
+// off of the stack, other references are borrowed userdata, and everything
+// else goes through `FromLuau`.
+pub fn arg(ty: &Type, name: &syn::Ident, narg: u32) -> (TokenStream, TokenStream) {
+    let Type::Reference(reference) = ty else {
+        return (
+            quote! { let #name = ctx.arg::<#ty>(#narg); },
+            quote! { #name },
+        );
+    };
+
+    let elem = &reference.elem;
+
+    match &**elem {
+        _ if reference.mutability.is_some() => (
+            quote! { let mut #name = ctx.arg_userdata_mut::<#elem>(#narg); },
+            quote! { &mut #name },
+        ),
+        elem if is(elem, "str") => (
+            quote! { let #name = ctx.arg_string_str(#narg); },
+            quote! { #name },
+        ),
+        Type::Slice(slice) if is(&slice.elem, "u8") => (
+            quote! { let #name = ctx.arg_string_slice(#narg); },
+            quote! { #name },
+        ),
+        _ => (
+            quote! { let #name = ctx.arg_userdata_ref::<#elem>(#narg); },
+            quote! { &#name },
+        ),
+    }
+}
+
+// Returns the expression for the Luau type of an argument read by `arg`.
+pub fn luau_type(ty: &Type) -> TokenStream {
+    match ty {
+        Type::Reference(reference)
+            if reference.mutability.is_none() && is_string(&reference.elem) =>
+        {
+            quote! { ::std::string::String::from("string") }
+        }
+        Type::Reference(reference) => {
+            let elem = &reference.elem;
+            quote! { <#elem as ::lu::Userdata>::name().to_owned() }
+        }
+        ty => quote! { <#ty as ::lu::FromLuau>::luau_type() },
+    }
+}
+
+// Tuples are returned as multiple values, including inside of a `Result`.
+// Returns the expression that wraps the result and the type whose `Returns`
+// impl describes it.
+pub fn returns(output: &ReturnType) -> (TokenStream, TokenStream) {
+    let ty = match output {
+        ReturnType::Default => return (quote! { result }, quote! { () }),
+        ReturnType::Type(_, ty) => &**ty,
+    };
+
+    let is_multi = |ty: &Type| matches!(ty, Type::Tuple(tuple) if !tuple.elems.is_empty());
+
+    if is_multi(ty) {
+        return (quote! { ::lu::Multi(result) }, quote! { ::lu::Multi<#ty> });
+    }
+
+    if let Type::Path(path) = ty
+        && let Some(segment) = path.path.segments.last()
+        && segment.ident == "Result"
+        && let PathArguments::AngleBracketed(args) = &segment.arguments
+        && let Some(GenericArgument::Type(ok)) = args.args.first()
+        && is_multi(ok)
+    {
+        return (
+            quote! { result.map(::lu::Multi) },
+            quote! { ::lu::Multi<#ok> },
+        );
+    }
+
+    (quote! { result }, quote! { #ty })
+}
+
+// Arguments without a plain name are numbered from 1.
+pub fn arg_name(arg: &PatType, i: usize) -> String {
+    match &*arg.pat {
+        Pat::Ident(pat) => pat.ident.to_string(),
+        _ => format!("arg{}", i + 1),
+    }
+}
+
+// Returns the expression that builds a signature like
+// `(a: number, b: number?) -> number`.
+pub fn signature(args: &[(String, Type)], returns_ty: &TokenStream) -> TokenStream {
+    let names = args.iter().map(|(name, _)| name);
+    let types = args.iter().map(|(_, ty)| luau_type(ty));
+
+    quote! {
+        ::std::format!(
+            "({}) -> {}",
+            <[::std::string::String]>::join(
+                &[#(::std::format!("{}: {}", #names, #types)),*],
+                ", ",
+            ),
+            <#returns_ty as ::lu::Returns>::returns_type(),
+        )
+    }
+}
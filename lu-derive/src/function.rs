@@ -0,0 +1,119 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{FnArg, ItemFn, LitStr};
+
+use crate::args::{arg, arg_name, returns, signature};
+
+pub struct Attrs {
+    name: Option<LitStr>,
+    associated: bool,
+}
+
+impl syn::parse::Parse for Attrs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let mut name = None;
+        let mut associated = false;
+
+        let parser = syn::meta::parser(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("associated") {
+                associated = true;
+                Ok(())
+            } else {
+                Err(meta.error("unknown lu function attribute"))
+            }
+        });
+
+        syn::parse::Parser::parse2(parser, input.parse()?)?;
+
+        Ok(Self { name, associated })
+    }
+}
+
+// The function is kept as is, with an `extern` shim and a `<ident>_function`
+// constructor for the `TypedFunction` emitted beside it. Functions in impl
+// blocks are `associated`, so the generated items refer to it through `Self`.
+pub fn function(attrs: Attrs, input: ItemFn) -> syn::Result<TokenStream> {
+    let sig = &input.sig;
+
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &sig.generics,
+            "lu functions cannot be generic",
+        ));
+    }
+
+    if let Some(asyncness) = &sig.asyncness {
+        return Err(syn::Error::new_spanned(
+            asyncness,
+            "lu functions cannot be async",
+        ));
+    }
+
+    let mut args = Vec::new();
+
+    for input in &sig.inputs {
+        let FnArg::Typed(arg) = input else {
+            return Err(syn::Error::new_spanned(
+                input,
+                "lu functions cannot take self",
+            ));
+        };
+
+        args.push((arg_name(arg, args.len()), (*arg.ty).clone()));
+    }
+
+    let ident = &sig.ident;
+    let vis = &input.vis;
+    let shim = format_ident!("__lu_shim_{}", ident);
+    let constructor = format_ident!("{}_function", ident);
+    let name = attrs
+        .name
+        .map(|name| name.value())
+        .unwrap_or_else(|| ident.to_string());
+
+    let prefix = if attrs.associated {
+        quote! { Self:: }
+    } else {
+        quote! {}
+    };
+
+    let (parsed, passed): (Vec<_>, Vec<_>) = args
+        .iter()
+        .enumerate()
+        .map(|(i, (_, ty))| arg(ty, &format_ident!("arg{}", i), i as u32 + 1))
+        .unzip();
+
+    let (wrap, returns_ty) = returns(&sig.output);
+
+    let signature = signature(&args, &returns_ty);
+    let nargs = args.len() as i32;
+    let extra = args.len() as u32 + 1;
+
+    Ok(quote! {
+        #input
+
+        #[doc(hidden)]
+        #[allow(non_snake_case)]
+        extern "C-unwind" fn #shim<C: ::lu::Config>(ctx: ::lu::Context<C>) -> ::lu::FnReturn {
+            if ctx.get_top() > #nargs {
+                ctx.arg_error(#extra, c"too many arguments");
+            }
+
+            let result = {
+                #(#parsed)*
+
+                #prefix #ident(#(#passed),*)
+            };
+
+            let n = ::lu::Returns::push_returns(#wrap, &ctx);
+            ctx.ret_with(n)
+        }
+
+        #vis fn #constructor<C: ::lu::Config>() -> ::lu::TypedFunction<C> {
+            ::lu::TypedFunction::new(::lu::Function::norm(#name, #prefix #shim::<C>), #signature)
+        }
+    })
+}
@@ -1,27 +1,76 @@
-use quote::quote;
-use syn::DeriveInput;
+mod args;
+mod function;
+mod library;
+mod light;
+mod methods;
+mod userdata;
 
-#[proc_macro_derive(Userdata)]
+#[proc_macro_derive(Userdata, attributes(lu))]
 pub fn userdata_derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let DeriveInput {
-        ident, generics, ..
-    } = syn::parse_macro_input!(input as DeriveInput);
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
 
-    if !generics.params.is_empty() {
-        panic!("Userdata derive macro does not support generics");
-    }
+    userdata::derive(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
 
-    quote! {
-        impl ::lu::Userdata for #ident {
-            fn tag() -> u32 {
-                static TAG: ::std::sync::OnceLock<u32> = ::std::sync::OnceLock::new();
-                *TAG.get_or_init(::lu::unique_tag)
-            }
-
-            fn name() -> &'static str {
-                stringify!(#ident)
-            }
-        }
+#[proc_macro_derive(LightUserdata, attributes(lu))]
+pub fn light_userdata_derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
+
+    light::derive(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_attribute]
+pub fn methods(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let attr = proc_macro2::TokenStream::from(attr);
+    if !attr.is_empty() {
+        return syn::Error::new_spanned(attr, "lu::methods takes no arguments")
+            .into_compile_error()
+            .into();
     }
-    .into()
+
+    let input = syn::parse_macro_input!(item as syn::ItemImpl);
+
+    methods::methods(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_attribute]
+pub fn function(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let attrs = syn::parse_macro_input!(attr as function::Attrs);
+    let input = syn::parse_macro_input!(item as syn::ItemFn);
+
+    function::function(attrs, input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_attribute]
+pub fn library(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let attrs = syn::parse_macro_input!(attr as library::Attrs);
+
+    let result = match syn::parse::<syn::Item>(item) {
+        Ok(syn::Item::Mod(input)) => library::library_mod(attrs, input),
+        Ok(syn::Item::Impl(input)) => library::library_impl(attrs, input),
+        Ok(item) => Err(syn::Error::new_spanned(
+            item,
+            "lu::library can only be used on modules and impl blocks",
+        )),
+        Err(err) => Err(err),
+    };
+
+    result.unwrap_or_else(syn::Error::into_compile_error).into()
 }
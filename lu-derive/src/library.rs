@@ -0,0 +1,211 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Attribute, ImplItem, Item, ItemImpl, ItemMod, LitStr, Visibility};
+
+pub struct Attrs {
+    name: Option<LitStr>,
+}
+
+impl syn::parse::Parse for Attrs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let mut name = None;
+
+        let parser = syn::meta::parser(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unknown lu library attribute"))
+            }
+        });
+
+        syn::parse::Parser::parse2(parser, input.parse()?)?;
+
+        Ok(Self { name })
+    }
+}
+
+fn is_lu_attr(attr: &Attribute, name: &str) -> bool {
+    let segments = &attr.path().segments;
+
+    match segments.len() {
+        1 => segments[0].ident == name,
+        2 => segments[0].ident == "lu" && segments[1].ident == name,
+        _ => false,
+    }
+}
+
+// Functions that aren't already `#[lu::function]`s are turned into one.
+// Those in impl blocks are marked `associated`.
+fn mark_function(attrs: &mut Vec<Attribute>, associated: bool) {
+    let Some(attr) = attrs.iter_mut().find(|attr| is_lu_attr(attr, "function")) else {
+        attrs.push(match associated {
+            true => syn::parse_quote!(#[::lu::function(associated)]),
+            false => syn::parse_quote!(#[::lu::function]),
+        });
+        return;
+    };
+
+    if !associated {
+        return;
+    }
+
+    let path = attr.path().clone();
+    *attr = match &attr.meta {
+        syn::Meta::List(list) => {
+            let tokens = &list.tokens;
+            syn::parse_quote!(#[#path(#tokens, associated)])
+        }
+        _ => syn::parse_quote!(#[#path(associated)]),
+    };
+}
+
+fn is_pub(vis: &Visibility) -> bool {
+    matches!(vis, Visibility::Public(_))
+}
+
+pub fn library_mod(attrs: Attrs, mut input: ItemMod) -> syn::Result<TokenStream> {
+    let name = attrs
+        .name
+        .map(|name| name.value())
+        .unwrap_or_else(|| input.ident.to_string());
+
+    let Some((_, items)) = &mut input.content else {
+        return Err(syn::Error::new_spanned(
+            &input,
+            "lu::library modules must be inline",
+        ));
+    };
+
+    let mut entries = Vec::new();
+
+    for item in items.iter_mut() {
+        match item {
+            Item::Const(item) if is_pub(&item.vis) => {
+                let ident = &item.ident;
+                let key = ident.to_string();
+
+                entries.push(quote! { let library = library.with_constant(#key, #ident); });
+            }
+            Item::Fn(item) if is_pub(&item.vis) => {
+                let constructor = format_ident!("{}_function", item.sig.ident);
+                mark_function(&mut item.attrs, false);
+
+                entries.push(quote! {
+                    let func = #constructor::<C>();
+                    let library = library.with_typed_function(func.name(), func);
+                });
+            }
+            Item::Mod(item) if is_pub(&item.vis) => {
+                let ident = item.ident.clone();
+
+                // Nested modules are sub-libraries, expanded here so that their
+                // names are known.
+                let attrs = match item
+                    .attrs
+                    .iter()
+                    .position(|attr| is_lu_attr(attr, "library"))
+                {
+                    Some(i) => match item.attrs.remove(i).meta {
+                        syn::Meta::List(list) => syn::parse2(list.tokens)?,
+                        _ => Attrs { name: None },
+                    },
+                    None => Attrs { name: None },
+                };
+
+                let key = attrs
+                    .name
+                    .as_ref()
+                    .map(LitStr::value)
+                    .unwrap_or_else(|| ident.to_string());
+
+                *item = syn::parse2(library_mod(attrs, item.clone())?)?;
+                entries.push(quote! { let library = library.with(#key, #ident::library::<C>()); });
+            }
+            _ => {}
+        }
+    }
+
+    items.push(syn::parse_quote! {
+        pub const LIBRARY_NAME: &str = #name;
+    });
+
+    items.push(syn::parse_quote! {
+        pub fn library<C: ::lu::Config>() -> ::lu::Library<C> {
+            let library = ::lu::Library::default();
+            #(#entries)*
+            library
+        }
+    });
+
+    Ok(quote! { #input })
+}
+
+pub fn library_impl(attrs: Attrs, mut input: ItemImpl) -> syn::Result<TokenStream> {
+    if let Some((_, path, _)) = &input.trait_ {
+        return Err(syn::Error::new_spanned(
+            path,
+            "lu::library cannot be used on trait impls",
+        ));
+    }
+
+    let name = match attrs.name {
+        Some(name) => name.value(),
+        None => match &*input.self_ty {
+            syn::Type::Path(path) => path.path.segments.last().unwrap().ident.to_string(),
+            ty => {
+                return Err(syn::Error::new_spanned(
+                    ty,
+                    "lu::library needs a name for this type",
+                ));
+            }
+        },
+    };
+
+    let mut entries = Vec::new();
+
+    for item in &mut input.items {
+        match item {
+            ImplItem::Const(item) if is_pub(&item.vis) => {
+                let ident = &item.ident;
+                let key = ident.to_string();
+
+                entries.push(quote! { let library = library.with_constant(#key, Self::#ident); });
+            }
+            ImplItem::Fn(item) if is_pub(&item.vis) => {
+                if let Some(receiver) = item.sig.receiver() {
+                    return Err(syn::Error::new_spanned(
+                        receiver,
+                        "lu::library functions cannot take self",
+                    ));
+                }
+
+                let constructor = format_ident!("{}_function", item.sig.ident);
+                mark_function(&mut item.attrs, true);
+
+                entries.push(quote! {
+                    let func = Self::#constructor::<C>();
+                    let library = library.with_typed_function(func.name(), func);
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let (impl_generics, _, where_clause) = input.generics.split_for_impl();
+    let self_ty = &input.self_ty;
+
+    Ok(quote! {
+        #input
+
+        impl #impl_generics #self_ty #where_clause {
+            pub const LIBRARY_NAME: &str = #name;
+
+            pub fn library<C: ::lu::Config>() -> ::lu::Library<C> {
+                let library = ::lu::Library::default();
+                #(#entries)*
+                library
+            }
+        }
+    })
+}
@@ -0,0 +1,45 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{DeriveInput, LitStr};
+
+pub fn derive(input: DeriveInput) -> syn::Result<TokenStream> {
+    let DeriveInput {
+        attrs,
+        ident,
+        generics,
+        ..
+    } = input;
+
+    if !generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            generics,
+            "light userdata types cannot be generic",
+        ));
+    }
+
+    let mut name = ident.to_string();
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("lu")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else {
+                Err(meta.error("unknown lu attribute"))
+            }
+        })?;
+    }
+
+    Ok(quote! {
+        impl ::lu::LightUserdata for #ident {
+            fn tag() -> u32 {
+                static TAG: ::std::sync::OnceLock<u32> = ::std::sync::OnceLock::new();
+                *TAG.get_or_init(::lu::unique_light_tag)
+            }
+
+            fn name() -> &'static str {
+                #name
+            }
+        }
+    })
+}
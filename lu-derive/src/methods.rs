@@ -0,0 +1,207 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{FnArg, ImplItem, ImplItemFn, ItemImpl, LitStr, ReturnType, Type};
+
+use crate::args::{arg, arg_name, returns, signature};
+
+struct Method {
+    name: String,
+    ident: syn::Ident,
+    mutable: bool,
+    args: Vec<(String, Type)>,
+    output: ReturnType,
+}
+
+// Takes the `#[lu(...)]` attributes off of a method, returning `None` if it
+// is skipped.
+fn parse_attrs(func: &mut ImplItemFn) -> syn::Result<Option<String>> {
+    let mut name = func.sig.ident.to_string();
+    let mut skip = false;
+
+    for attr in func.attrs.iter().filter(|attr| attr.path().is_ident("lu")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                skip = true;
+            } else if meta.path.is_ident("name") {
+                name = meta.value()?.parse::<LitStr>()?.value();
+            } else {
+                return Err(meta.error("unknown lu method attribute"));
+            }
+
+            Ok(())
+        })?;
+    }
+
+    func.attrs.retain(|attr| !attr.path().is_ident("lu"));
+
+    Ok((!skip).then_some(name))
+}
+
+fn parse_method(func: &ImplItemFn, name: String) -> syn::Result<Method> {
+    let sig = &func.sig;
+
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &sig.generics,
+            "lu methods cannot be generic",
+        ));
+    }
+
+    if let Some(asyncness) = &sig.asyncness {
+        return Err(syn::Error::new_spanned(
+            asyncness,
+            "lu methods cannot be async",
+        ));
+    }
+
+    let mut inputs = sig.inputs.iter();
+
+    let mutable = match inputs.next() {
+        Some(FnArg::Receiver(receiver)) if receiver.reference.is_some() => {
+            receiver.mutability.is_some()
+        }
+        Some(FnArg::Receiver(receiver)) => {
+            return Err(syn::Error::new_spanned(
+                receiver,
+                "lu methods must take &self or &mut self",
+            ));
+        }
+        _ => unreachable!("methods without a receiver are filtered out"),
+    };
+
+    let args = inputs
+        .enumerate()
+        .map(|(i, arg)| match arg {
+            FnArg::Typed(arg) => Ok((arg_name(arg, i), (*arg.ty).clone())),
+            FnArg::Receiver(receiver) => {
+                Err(syn::Error::new_spanned(receiver, "unexpected receiver"))
+            }
+        })
+        .collect::<syn::Result<_>>()?;
+
+    Ok(Method {
+        name,
+        ident: sig.ident.clone(),
+        mutable,
+        args,
+        output: sig.output.clone(),
+    })
+}
+
+fn shim(method: &Method) -> (syn::Ident, TokenStream) {
+    let Method {
+        ident,
+        mutable,
+        args,
+        output,
+        ..
+    } = method;
+
+    let shim = format_ident!("__lu_method_{}", ident);
+
+    let (parsed, passed): (Vec<_>, Vec<_>) = args
+        .iter()
+        .enumerate()
+        .map(|(i, (_, ty))| arg(ty, &format_ident!("arg{}", i), i as u32 + 2))
+        .unzip();
+
+    let this = if *mutable {
+        quote! { let mut this = ctx.arg_userdata_mut::<Self>(1); }
+    } else {
+        quote! { let this = ctx.arg_userdata_ref::<Self>(1); }
+    };
+
+    let receiver = if *mutable {
+        quote! { &mut this }
+    } else {
+        quote! { &this }
+    };
+
+    let (wrap, _) = returns(output);
+
+    // The borrows are released before the results are pushed, since pushing
+    // an `Err` raises an error.
+    let tokens = quote! {
+        #[doc(hidden)]
+        #[allow(non_snake_case)]
+        extern "C-unwind" fn #shim<C: ::lu::Config>(ctx: ::lu::Context<C>) -> ::lu::FnReturn {
+            let result = {
+                #this
+                #(#parsed)*
+
+                Self::#ident(#receiver, #(#passed),*)
+            };
+
+            let n = ::lu::Returns::push_returns(#wrap, &ctx);
+            ctx.ret_with(n)
+        }
+    };
+
+    (shim, tokens)
+}
+
+pub fn methods(mut input: ItemImpl) -> syn::Result<TokenStream> {
+    if let Some((_, path, _)) = &input.trait_ {
+        return Err(syn::Error::new_spanned(
+            path,
+            "lu::methods cannot be used on trait impls",
+        ));
+    }
+
+    let mut methods = Vec::new();
+
+    for item in &mut input.items {
+        let ImplItem::Fn(func) = item else {
+            continue;
+        };
+
+        let Some(name) = parse_attrs(func)? else {
+            continue;
+        };
+
+        if func.sig.receiver().is_none() {
+            continue;
+        }
+
+        methods.push(parse_method(func, name)?);
+    }
+
+    let (shims, registrations): (Vec<_>, Vec<_>) = methods
+        .iter()
+        .map(|method| {
+            let (ident, tokens) = shim(method);
+            let name = &method.name;
+            let (_, returns_ty) = returns(&method.output);
+            let signature = signature(&method.args, &returns_ty);
+
+            (
+                tokens,
+                quote! {
+                    .with_typed_method(
+                        #name,
+                        ::lu::TypedFunction::new(
+                            ::lu::Function::norm(#name, Self::#ident::<C>),
+                            #signature,
+                        ),
+                    )
+                },
+            )
+        })
+        .unzip();
+
+    let (impl_generics, _, where_clause) = input.generics.split_for_impl();
+    let self_ty = &input.self_ty;
+
+    Ok(quote! {
+        #input
+
+        impl #impl_generics #self_ty #where_clause {
+            #(#shims)*
+
+            pub fn methods<C: ::lu::Config>() -> ::lu::Methods<C> {
+                ::lu::Methods::default()
+                    #(#registrations)*
+            }
+        }
+    })
+}
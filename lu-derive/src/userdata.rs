@@ -0,0 +1,253 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, GenericParam, LitStr, parse_quote};
+
+struct Field {
+    ident: syn::Ident,
+    ty: syn::Type,
+    name: String,
+    readonly: bool,
+}
+
+fn parse_fields(data: &Data) -> syn::Result<Vec<Field>> {
+    let fields = match data {
+        Data::Struct(data) => &data.fields,
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut parsed = Vec::new();
+
+    for field in fields {
+        let mut is_field = false;
+        let mut name = None;
+        let mut readonly = false;
+
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("lu")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("field") {
+                    is_field = true;
+                } else if meta.path.is_ident("readonly") {
+                    readonly = true;
+                } else if meta.path.is_ident("name") {
+                    name = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else {
+                    return Err(meta.error("unknown lu field attribute"));
+                }
+
+                Ok(())
+            })?;
+        }
+
+        if !is_field {
+            continue;
+        }
+
+        let Some(ident) = field.ident.clone() else {
+            return Err(syn::Error::new_spanned(
+                field,
+                "lu fields must be named struct fields",
+            ));
+        };
+
+        parsed.push(Field {
+            ty: field.ty.clone(),
+            name: name.unwrap_or_else(|| ident.to_string()),
+            ident,
+            readonly,
+        });
+    }
+
+    Ok(parsed)
+}
+
+fn add_bounds(generics: &mut syn::Generics, fields: &[Field]) {
+    let type_params = generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect::<Vec<_>>();
+
+    let where_clause = generics.make_where_clause();
+    for param in type_params {
+        where_clause
+            .predicates
+            .push(parse_quote! { #param: 'static });
+    }
+
+    for Field { ty, readonly, .. } in fields {
+        where_clause.predicates.push(if *readonly {
+            parse_quote! { #ty: ::lu::IntoLuau + ::core::clone::Clone }
+        } else {
+            parse_quote! { #ty: ::lu::IntoLuau + ::lu::FromLuau + ::core::clone::Clone }
+        });
+    }
+}
+
+pub fn derive(input: DeriveInput) -> syn::Result<TokenStream> {
+    let DeriveInput {
+        attrs,
+        ident,
+        generics,
+        data,
+        ..
+    } = input;
+
+    let mut name = ident.to_string();
+    let mut uservalue = false;
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("lu")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = meta.value()?.parse::<LitStr>()?.value();
+            } else if meta.path.is_ident("uservalue") {
+                uservalue = true;
+            } else {
+                return Err(meta.error("unknown lu attribute"));
+            }
+
+            Ok(())
+        })?;
+    }
+
+    let uservalue = if uservalue {
+        quote! {
+            fn has_uservalue() -> bool {
+                true
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let fields = parse_fields(&data)?;
+
+    let field_impls = if fields.is_empty() {
+        quote! {}
+    } else {
+        let names = fields.iter().map(|field| &field.name);
+
+        let types = fields.iter().map(|Field { ty, name, .. }| {
+            quote! { #name => ::core::option::Option::Some(<#ty as ::lu::IntoLuau>::luau_type()), }
+        });
+
+        let getters = fields.iter().map(|Field { ident, name, .. }| {
+            quote! {
+                #name => {
+                    stack.reserve(1);
+                    ::lu::IntoLuau::push(::core::clone::Clone::clone(&self.#ident), stack);
+                    true
+                }
+            }
+        });
+
+        let setters = fields.iter().map(
+            |Field {
+                 ident,
+                 name,
+                 readonly,
+                 ..
+             }| {
+                if *readonly {
+                    let msg = format!("field '{name}' is read-only");
+                    quote! { #name => ctx.error_msg(#msg), }
+                } else {
+                    quote! {
+                        #name => {
+                            self.#ident = ctx.arg(narg);
+                            true
+                        }
+                    }
+                }
+            },
+        );
+
+        quote! {
+            fn fields() -> &'static [&'static str] {
+                &[#(#names),*]
+            }
+
+            fn field_type(field: &str) -> ::core::option::Option<::std::string::String> {
+                match field {
+                    #(#types)*
+                    _ => ::core::option::Option::None,
+                }
+            }
+
+            fn get_field<C: ::lu::Config>(&self, stack: &::lu::Stack<C>, field: &str) -> bool {
+                match field {
+                    #(#getters)*
+                    _ => false,
+                }
+            }
+
+            fn set_field<C: ::lu::Config>(
+                &mut self,
+                ctx: &::lu::Context<C>,
+                field: &str,
+                narg: u32,
+            ) -> bool {
+                match field {
+                    #(#setters)*
+                    _ => false,
+                }
+            }
+        }
+    };
+
+    // Generic types get a tag and name per monomorphization, which needs
+    // every parameter to be 'static so that it has a `TypeId`.
+    let (tag, name) = if generics.params.is_empty() {
+        let tag = quote! {
+            static TAG: ::std::sync::OnceLock<u32> = ::std::sync::OnceLock::new();
+            *TAG.get_or_init(::lu::unique_tag)
+        };
+
+        (tag, quote! { #name })
+    } else {
+        let args = generics
+            .params
+            .iter()
+            .map(|param| match param {
+                GenericParam::Type(param) => {
+                    let ident = &param.ident;
+                    Ok(quote! { ::std::any::type_name::<#ident>().to_owned() })
+                }
+                GenericParam::Const(param) => {
+                    let ident = &param.ident;
+                    Ok(quote! { #ident.to_string() })
+                }
+                GenericParam::Lifetime(param) => Err(syn::Error::new_spanned(
+                    param,
+                    "userdata types cannot have lifetime parameters",
+                )),
+            })
+            .collect::<syn::Result<Vec<_>>>()?;
+
+        let tag = quote! { ::lu::unique_tag_for::<Self>() };
+        let name = quote! { ::lu::generic_name_for::<Self>(#name, || vec![#(#args),*]) };
+
+        (tag, name)
+    };
+
+    let mut generics = generics;
+    if !generics.params.is_empty() {
+        add_bounds(&mut generics, &fields);
+    }
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::lu::Userdata for #ident #ty_generics #where_clause {
+            fn tag() -> u32 {
+                #tag
+            }
+
+            fn name() -> &'static str {
+                #name
+            }
+
+            #uservalue
+
+            #field_impls
+        }
+    })
+}
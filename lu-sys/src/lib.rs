@@ -51,3 +51,7 @@ pub use lua::*;
 pub use luacode::*;
 pub use luaconf::*;
 pub use lualib::*;
+
+/// The version of this crate. The minor version is the version of the bundled
+/// Luau, as described in [Stability and versioning](crate#stability-and-versioning).
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -25,6 +25,19 @@ pub type lua_LibraryMemberConstantCallback = extern "C" fn(
     constant: *mut lua_CompileConstant,
 );
 
+/// The oldest bytecode version that can be loaded by [`luau_load`](crate::luau_load).
+///
+/// These versions are defined in `Luau/Bytecode.h`, which is not bound, and
+/// change along with the bundled Luau. The first byte of bytecode is its
+/// version, or `0` if it holds a compile error instead.
+pub const LBC_VERSION_MIN: u8 = 3;
+
+/// The newest bytecode version that can be loaded by [`luau_load`](crate::luau_load).
+pub const LBC_VERSION_MAX: u8 = 6;
+
+/// The bytecode version produced by [`luau_compile`].
+pub const LBC_VERSION_TARGET: u8 = 6;
+
 /// Options to configure the Luau compiler with.
 ///
 /// All memory pointed to by this struct must be valid for the duration of the
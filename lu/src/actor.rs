@@ -0,0 +1,435 @@
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    ffi::{CString, c_void},
+    fmt,
+    ops::ControlFlow,
+    sync::{
+        Arc, Condvar, Mutex, RwLock,
+        atomic::{AtomicU32, Ordering},
+        mpsc::{Receiver, Sender, channel},
+    },
+    thread::JoinHandle,
+};
+
+use crate::{Config, Context, FnReturn, Library, SendableState, Stack, State, Status, Type};
+
+const MAILBOX_KEY: &std::ffi::CStr = c"lu.actor";
+
+pub type ActorId = u32;
+
+// Messages sent with `Runtime::send` come from the host rather than an actor.
+pub const HOST: ActorId = 0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    Vector(f32, f32, f32),
+    String(Vec<u8>),
+    Buffer(Vec<u8>),
+    Table(Vec<(Value, Value)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueError {
+    Unsupported(Type),
+    Cyclic,
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueError::Unsupported(ty) => write!(f, "cannot send a value of type {ty:?}"),
+            ValueError::Cyclic => write!(f, "cannot send a table that contains itself"),
+        }
+    }
+}
+
+impl std::error::Error for ValueError {}
+
+impl Value {
+    pub fn read<C: Config>(stack: &Stack<C>, idx: i32) -> Result<Self, ValueError> {
+        Self::read_inner(stack, stack.abs_idx(idx), &mut Vec::new())
+    }
+
+    fn read_inner<C: Config>(
+        stack: &Stack<C>,
+        idx: i32,
+        path: &mut Vec<*const c_void>,
+    ) -> Result<Self, ValueError> {
+        match stack.type_of(idx) {
+            Type::None | Type::Nil => Ok(Value::Nil),
+            Type::Boolean => Ok(Value::Boolean(stack.to_boolean_unchecked(idx))),
+            Type::Number => Ok(Value::Number(stack.to_number_unchecked(idx))),
+            Type::Vector => {
+                let (x, y, z) = unsafe { stack.to_vector_unchecked(idx) };
+                Ok(Value::Vector(x, y, z))
+            }
+            Type::String => Ok(Value::String(
+                unsafe { stack.to_string_slice_unchecked(idx) }.to_vec(),
+            )),
+            Type::Buffer => {
+                let (ptr, len) = unsafe { stack.to_buffer_unchecked(idx) };
+                Ok(Value::Buffer(
+                    unsafe { std::slice::from_raw_parts(ptr, len) }.to_vec(),
+                ))
+            }
+            Type::Table => Self::read_table(stack, idx, path),
+            ty => Err(ValueError::Unsupported(ty)),
+        }
+    }
+
+    fn read_table<C: Config>(
+        stack: &Stack<C>,
+        idx: i32,
+        path: &mut Vec<*const c_void>,
+    ) -> Result<Self, ValueError> {
+        let ptr = unsafe { sys::lua_topointer(stack.as_ptr(), idx) };
+        if path.contains(&ptr) {
+            return Err(ValueError::Cyclic);
+        }
+
+        path.push(ptr);
+        stack.reserve(2);
+
+        let top = stack.get_top();
+        let mut entries = Vec::new();
+
+        let error = stack.iter(idx, || {
+            let entry = Self::read_inner(stack, top + 1, path)
+                .and_then(|key| Ok((key, Self::read_inner(stack, top + 2, path)?)));
+
+            match entry {
+                Ok(entry) => {
+                    entries.push(entry);
+                    ControlFlow::Continue(())
+                }
+                Err(err) => ControlFlow::Break(err),
+            }
+        });
+
+        path.pop();
+
+        if let Some(err) = error {
+            stack.set_top(top);
+            Err(err)
+        } else {
+            Ok(Value::Table(entries))
+        }
+    }
+
+    pub fn push<C: Config>(&self, stack: &Stack<C>) {
+        stack.reserve(1);
+
+        match self {
+            Value::Nil => stack.push_nil(),
+            Value::Boolean(value) => stack.push_boolean(*value),
+            Value::Number(value) => stack.push_number(*value),
+            Value::Vector(x, y, z) => stack.push_vector((*x, *y, *z)),
+            Value::String(value) => stack.push_string(value),
+            Value::Buffer(value) => {
+                let (ptr, len) = stack.push_buffer(value.len());
+                unsafe { std::ptr::copy_nonoverlapping(value.as_ptr(), ptr, len) };
+            }
+            Value::Table(entries) => {
+                stack.push_table_with(0, entries.len() as _);
+                stack.reserve(2);
+
+                for (key, value) in entries {
+                    key.push(stack);
+                    value.push(stack);
+                    stack.table_set_raw(-3);
+                }
+            }
+        }
+    }
+}
+
+struct Envelope {
+    from: ActorId,
+    value: Value,
+}
+
+#[derive(Default)]
+struct Activity {
+    busy: usize,
+    pending: usize,
+}
+
+#[derive(Default)]
+struct Router {
+    next_id: AtomicU32,
+    peers: RwLock<HashMap<ActorId, Sender<Envelope>>>,
+    activity: Mutex<Activity>,
+    idle: Condvar,
+}
+
+impl Router {
+    fn deliver(&self, from: ActorId, to: ActorId, value: Value) -> bool {
+        self.activity.lock().unwrap().pending += 1;
+
+        let peers = self.peers.read().unwrap();
+        let sent = peers
+            .get(&to)
+            .is_some_and(|peer| peer.send(Envelope { from, value }).is_ok());
+        drop(peers);
+
+        if !sent {
+            self.update(|activity| activity.pending -= 1);
+        }
+
+        sent
+    }
+
+    fn update(&self, func: impl FnOnce(&mut Activity)) {
+        func(&mut self.activity.lock().unwrap());
+        self.idle.notify_all();
+    }
+}
+
+struct Mailbox {
+    id: ActorId,
+    router: Arc<Router>,
+    rx: Receiver<Envelope>,
+    thread: Cell<*mut sys::lua_State>,
+    waiting: Cell<bool>,
+    parked: Cell<bool>,
+}
+
+impl Drop for Mailbox {
+    fn drop(&mut self) {
+        self.router.peers.write().unwrap().remove(&self.id);
+
+        let undelivered = self.rx.try_iter().count();
+        let parked = self.parked.get();
+
+        self.router.update(|activity| {
+            activity.pending -= undelivered;
+
+            if !parked {
+                activity.busy -= 1;
+            }
+        });
+    }
+}
+
+impl Mailbox {
+    fn recv(&self) -> Option<Envelope> {
+        self.parked.set(true);
+        self.router.update(|activity| activity.busy -= 1);
+
+        let envelope = self.rx.recv().ok()?;
+
+        self.parked.set(false);
+        self.router.update(|activity| {
+            activity.busy += 1;
+            activity.pending -= 1;
+        });
+
+        Some(envelope)
+    }
+
+    fn get<C: Config>(ctx: &Context<C>) -> &Mailbox {
+        ctx.reserve(1);
+        ctx.table_get_raw_field(sys::LUA_REGISTRYINDEX, MAILBOX_KEY);
+
+        let ptr = ctx.to_light_userdata::<Mailbox>(-1);
+        ctx.pop(1);
+
+        match ptr {
+            Some(ptr) => unsafe { &*ptr },
+            None => ctx.error_msg("actor library used outside of an actor"),
+        }
+    }
+
+    fn run<C: Config>(&self, state: &State<C>) -> Result<(), String> {
+        if !state.stack().is_function(-1) {
+            return Err("actor entry point is not a function".into());
+        }
+
+        let thread = state.new_thread();
+        state.stack().xmove(&thread, 1);
+        self.thread.set(thread.as_ptr());
+
+        let stack = thread.stack();
+        let mut nargs = 0;
+
+        loop {
+            match thread.resume(None, nargs) {
+                Status::Ok => return Ok(()),
+                Status::Yield => {
+                    stack.set_top(0);
+                    nargs = 0;
+
+                    if self.waiting.replace(false) {
+                        let Some(envelope) = self.recv() else {
+                            return Ok(());
+                        };
+
+                        stack.reserve(2);
+                        envelope.value.push(stack);
+                        stack.push_number(envelope.from as _);
+                        nargs = 2;
+                    }
+                }
+                _ => {
+                    return Err(stack
+                        .to_string_str(-1)
+                        .unwrap_or("error object is not a string")
+                        .to_owned());
+                }
+            }
+        }
+    }
+}
+
+extern "C-unwind" fn actor_id<C: Config>(ctx: Context<C>) -> FnReturn {
+    let mailbox = Mailbox::get(&ctx);
+
+    ctx.push_number(mailbox.id as _);
+    ctx.ret_with(1)
+}
+
+extern "C-unwind" fn actor_send<C: Config>(ctx: Context<C>) -> FnReturn {
+    let mailbox = Mailbox::get(&ctx);
+
+    let to = ctx.arg_number(1);
+    if to.fract() != 0.0 || !(0.0..=ActorId::MAX as f64).contains(&to) {
+        ctx.arg_error(1, c"invalid actor id");
+    }
+
+    let value = Value::read(&ctx, 2).unwrap_or_else(|err| {
+        let reason = CString::new(err.to_string()).unwrap();
+        ctx.arg_error(2, reason.as_c_str())
+    });
+
+    let sent = mailbox.router.deliver(mailbox.id, to as _, value);
+
+    ctx.push_boolean(sent);
+    ctx.ret_with(1)
+}
+
+extern "C-unwind" fn actor_receive<C: Config>(ctx: Context<C>) -> FnReturn {
+    let mailbox = Mailbox::get(&ctx);
+
+    if ctx.as_ptr() != mailbox.thread.get() {
+        ctx.error_msg("actor.receive can only be called from the actor's main thread");
+    }
+
+    mailbox.waiting.set(true);
+    ctx.yld()
+}
+
+fn library<C: Config>() -> Library<C> {
+    Library::default()
+        .with_function_norm("id", actor_id)
+        .with_function_norm("send", actor_send)
+        .with_function_norm("receive", actor_receive)
+}
+
+pub struct Runtime {
+    router: Arc<Router>,
+    handles: Vec<(ActorId, JoinHandle<Result<(), String>>)>,
+}
+
+// Dropping the runtime without `join` closes every mailbox, which ends the
+// actors waiting in `actor.receive`. Actors that are still running are left
+// to finish in the background.
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        self.router.peers.write().unwrap().clear();
+    }
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        let router = Router {
+            next_id: AtomicU32::new(HOST + 1),
+            ..Default::default()
+        };
+
+        Self {
+            router: Arc::new(router),
+            handles: Vec::new(),
+        }
+    }
+
+    // `init` runs on the actor's thread and must leave the actor's entry
+    // point function on top of the main stack.
+    pub fn spawn<C, F>(&mut self, state: SendableState<C>, init: F) -> ActorId
+    where
+        C: Config,
+        SendableState<C>: Send + 'static,
+        F: FnOnce(&mut State<C>) + Send + 'static,
+    {
+        let id = self.router.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = channel();
+
+        self.router.peers.write().unwrap().insert(id, tx);
+        self.router.update(|activity| activity.busy += 1);
+
+        let router = self.router.clone();
+
+        let handle = std::thread::spawn(move || {
+            let mailbox = Mailbox {
+                id,
+                router,
+                rx,
+                thread: Cell::new(std::ptr::null_mut()),
+                waiting: Cell::new(false),
+                parked: Cell::new(false),
+            };
+
+            let mut state = state.into_inner();
+
+            let stack = state.stack();
+            stack.reserve(1);
+            stack.push_light_userdata(&mailbox as *const Mailbox as *mut Mailbox);
+            stack.table_set_raw_field(sys::LUA_REGISTRYINDEX, MAILBOX_KEY);
+
+            state.open_library("actor", library());
+            init(&mut state);
+
+            mailbox.run(&state)
+        });
+
+        self.handles.push((id, handle));
+        id
+    }
+
+    pub fn send(&self, to: ActorId, value: Value) -> bool {
+        self.router.deliver(HOST, to, value)
+    }
+
+    // Waits until every actor has either finished or is waiting in
+    // `actor.receive` with no messages left in flight, then shuts down the
+    // remaining actors.
+    pub fn join(mut self) -> Vec<(ActorId, Result<(), String>)> {
+        let mut activity = self.router.activity.lock().unwrap();
+        while activity.busy > 0 || activity.pending > 0 {
+            activity = self.router.idle.wait(activity).unwrap();
+        }
+        drop(activity);
+
+        self.router.peers.write().unwrap().clear();
+
+        std::mem::take(&mut self.handles)
+            .into_iter()
+            .map(|(id, handle)| {
+                let result = handle
+                    .join()
+                    .unwrap_or_else(|_| Err("actor thread panicked".into()));
+
+                (id, result)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,40 @@
+use std::{
+    collections::HashMap,
+    ffi::c_char,
+    sync::{OnceLock, RwLock},
+};
+
+// Atoms are shared by every `State`, so a method name has the same atom no
+// matter which VM or type registered it, and `useratom` doesn't need to know
+// which VM a string belongs to.
+fn atoms() -> &'static RwLock<HashMap<Box<[u8]>, i16>> {
+    static ATOMS: OnceLock<RwLock<HashMap<Box<[u8]>, i16>>> = OnceLock::new();
+    ATOMS.get_or_init(Default::default)
+}
+
+// Returns `None` once the atoms run out, leaving the name to be looked up
+// instead.
+pub(crate) fn atom(name: &str) -> Option<i16> {
+    if let Some(atom) = atoms().read().unwrap().get(name.as_bytes()) {
+        return Some(*atom);
+    }
+
+    let mut atoms = atoms().write().unwrap();
+
+    if let Some(atom) = atoms.get(name.as_bytes()) {
+        return Some(*atom);
+    }
+
+    let atom = i16::try_from(atoms.len()).ok()?;
+    atoms.insert(name.as_bytes().into(), atom);
+
+    Some(atom)
+}
+
+// Strings created before their name was registered keep the atom -1, which
+// `__namecall` handles by looking the method up by name.
+pub(crate) extern "C-unwind" fn useratom(s: *const c_char, l: usize) -> i16 {
+    let name = unsafe { std::slice::from_raw_parts(s.cast::<u8>(), l) };
+
+    atoms().read().unwrap().get(name).copied().unwrap_or(-1)
+}
@@ -0,0 +1,298 @@
+use std::{
+    collections::HashMap,
+    ffi::CStr,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use crate::{Bytecode, BytecodeBuf, Compiler, Config, Stack};
+
+// SHA-256, which is stable across builds unlike `DefaultHasher` and can't be
+// made to collide, so it can name files on disk.
+pub(crate) struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    filled: usize,
+    len: u64,
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+impl Sha256 {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: [0; 64],
+            filled: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn write(&mut self, mut bytes: &[u8]) {
+        self.len = self.len.wrapping_add(bytes.len() as u64);
+
+        while !bytes.is_empty() {
+            let n = (64 - self.filled).min(bytes.len());
+            self.block[self.filled..self.filled + n].copy_from_slice(&bytes[..n]);
+            self.filled += n;
+            bytes = &bytes[n..];
+
+            if self.filled == 64 {
+                self.compress();
+                self.filled = 0;
+            }
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> [u8; 32] {
+        let bits = self.len.wrapping_mul(8);
+
+        self.write(&[0x80]);
+        while self.filled != 56 {
+            self.write(&[0]);
+        }
+        self.write(&bits.to_be_bytes());
+
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+
+        digest
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+
+        for (i, chunk) in self.block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(chunk.try_into().unwrap());
+        }
+
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (word, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+}
+
+// The version byte of bytecode produced by the linked compiler.
+fn bytecode_version() -> u8 {
+    static VERSION: OnceLock<u8> = OnceLock::new();
+
+    *VERSION.get_or_init(|| Compiler::default().compile(b"").into_owned().version())
+}
+
+// Compiled bytecode keyed by the source and compiler options. Entries live in
+// memory, and also in a directory when one is given, under a subdirectory for
+// the Luau and bytecode versions so that a different Luau never reads them.
+pub struct BytecodeCache {
+    compiler: Compiler,
+    dir: Option<PathBuf>,
+    entries: HashMap<Key, Entry>,
+}
+
+type Key = [u8; 32];
+
+// Entries in memory keep their source, so a lookup only hits when the source
+// really is the same.
+struct Entry {
+    source: Box<[u8]>,
+    bytecode: BytecodeBuf,
+}
+
+// Files on disk start with the key and the length of the source they were
+// compiled from, which are checked before the bytecode that follows is used.
+const HEADER_LEN: usize = 32 + 8;
+
+impl BytecodeCache {
+    pub fn new(compiler: Compiler) -> Self {
+        Self {
+            compiler,
+            dir: None,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        let version = format!("luau-{}-v{}", sys::VERSION, bytecode_version());
+        self.dir = Some(dir.into().join(version));
+        self
+    }
+
+    pub fn compiler(&self) -> &Compiler {
+        &self.compiler
+    }
+
+    // Compiles `source`, or returns the cached bytecode for it. Compile
+    // errors are cached in memory, but never written to disk.
+    pub fn get(&mut self, source: &[u8]) -> Bytecode<'_> {
+        let key = self.key(source);
+
+        if self
+            .entries
+            .get(&key)
+            .is_none_or(|entry| *entry.source != *source)
+        {
+            let bytecode = self
+                .read(&key, source)
+                .unwrap_or_else(|| self.compile(&key, source));
+
+            let entry = Entry {
+                source: source.into(),
+                bytecode,
+            };
+
+            self.entries.insert(key, entry);
+        }
+
+        self.entries[&key].bytecode.bytecode()
+    }
+
+    // Pushes the compiled chunk, or returns the compile or load error without
+    // pushing anything.
+    pub fn load<C: Config>(
+        &mut self,
+        stack: &Stack<C>,
+        name: &CStr,
+        source: &[u8],
+    ) -> Result<(), String> {
+        let bytecode = self.get(source);
+        bytecode.result().map_err(str::to_owned)?;
+
+        stack.push_bytecode(name, bytecode)
+    }
+
+    // Forgets the entries in memory, leaving those on disk.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn key(&self, source: &[u8]) -> Key {
+        let mut hash = Sha256::new();
+
+        hash.write(&(source.len() as u64).to_le_bytes());
+        hash.write(source);
+        self.compiler.hash_options(&mut hash);
+
+        hash.finish()
+    }
+
+    fn path(&self, key: &Key) -> Option<PathBuf> {
+        let name: String = key.iter().map(|byte| format!("{byte:02x}")).collect();
+
+        Some(self.dir.as_deref()?.join(name + ".luauc"))
+    }
+
+    fn read(&self, key: &Key, source: &[u8]) -> Option<BytecodeBuf> {
+        let mut bytes = fs::read(self.path(key)?).ok()?;
+
+        if bytes.len() < HEADER_LEN || bytes[..HEADER_LEN] != header(key, source) {
+            return None;
+        }
+
+        let bytecode = BytecodeBuf::new(bytes.split_off(HEADER_LEN)).ok()?;
+
+        (bytecode.version() == bytecode_version()).then_some(bytecode)
+    }
+
+    // Failing to write to the directory only means the next run compiles
+    // again, so errors are ignored.
+    fn compile(&self, key: &Key, source: &[u8]) -> BytecodeBuf {
+        let bytecode = self.compiler.compile(source).into_owned();
+
+        if bytecode.bytecode().result().is_ok()
+            && let Some(path) = self.path(key)
+        {
+            let _ = write(&path, &header(key, source), &bytecode);
+        }
+
+        bytecode
+    }
+}
+
+fn header(key: &Key, source: &[u8]) -> [u8; HEADER_LEN] {
+    let mut header = [0; HEADER_LEN];
+
+    header[..32].copy_from_slice(key);
+    header[32..].copy_from_slice(&(source.len() as u64).to_le_bytes());
+
+    header
+}
+
+// Writes through a temporary file so that other processes, and other caches
+// in this one, never see a partially written entry. Each write gets its own
+// temporary file, which is created fresh rather than reused.
+fn write(path: &Path, header: &[u8], bytecode: &BytecodeBuf) -> std::io::Result<()> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let tmp = path.with_extension(format!("tmp{}-{count}", std::process::id()));
+
+    let result = fs::File::create_new(&tmp)
+        .and_then(|mut file| {
+            file.write_all(header)?;
+            file.write_all(bytecode.as_slice())
+        })
+        .and_then(|()| fs::rename(&tmp, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+
+    result
+}
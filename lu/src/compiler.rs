@@ -1,6 +1,12 @@
-use std::ffi::c_char;
+use std::ffi::{CStr, c_char};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
 use std::ptr::null;
 
+use crate::cache::Sha256;
+
 #[derive(Default, Clone, Copy)]
 pub enum OptimizationLevel {
     None,
@@ -54,8 +60,51 @@ impl Compiler {
         self
     }
 
-    pub fn compile(&self, source: &[u8]) -> CompileResult {
-        let mut options = sys::lua_CompileOptions {
+    // Feeds the options passed to the compiler into a cache key. Every field
+    // is named so that a new option can't be missed. Callbacks can only be
+    // told apart by whether they're set.
+    pub(crate) fn hash_options(&self, hash: &mut Sha256) {
+        let sys::lua_CompileOptions {
+            optimizationLevel,
+            debugLevel,
+            typeInfoLevel,
+            coverageLevel,
+            vectorLib,
+            vectorCtor,
+            vectorType,
+            mutableGlobals,
+            userdataTypes,
+            librariesWithKnownMembers,
+            libraryMemberTypeCb,
+            libraryMemberConstantCb,
+            disabledBuiltins,
+        } = self.options();
+
+        for level in [optimizationLevel, debugLevel, typeInfoLevel, coverageLevel] {
+            hash.write(&level.to_le_bytes());
+        }
+
+        for string in [vectorLib, vectorCtor, vectorType] {
+            hash_string(hash, string);
+        }
+
+        for list in [
+            mutableGlobals,
+            userdataTypes,
+            librariesWithKnownMembers,
+            disabledBuiltins,
+        ] {
+            hash_list(hash, list);
+        }
+
+        hash.write(&[
+            libraryMemberTypeCb.is_some() as u8,
+            libraryMemberConstantCb.is_some() as u8,
+        ]);
+    }
+
+    fn options(&self) -> sys::lua_CompileOptions {
+        sys::lua_CompileOptions {
             optimizationLevel: self.optimization_level.into(),
             debugLevel: self.debug_level.into(),
             typeInfoLevel: 0,
@@ -69,7 +118,11 @@ impl Compiler {
             libraryMemberTypeCb: None,
             libraryMemberConstantCb: None,
             disabledBuiltins: null(),
-        };
+        }
+    }
+
+    pub fn compile(&self, source: &[u8]) -> CompileResult {
+        let mut options = self.options();
 
         let mut len = 0;
         let ptr = unsafe {
@@ -80,6 +133,35 @@ impl Compiler {
     }
 }
 
+// Null strings are told apart from empty ones by a leading byte.
+fn hash_string(hash: &mut Sha256, string: *const c_char) {
+    if string.is_null() {
+        hash.write(&[0]);
+    } else {
+        hash.write(&[1]);
+        hash.write(unsafe { CStr::from_ptr(string) }.to_bytes_with_nul());
+    }
+}
+
+// Hashes a null-terminated array of strings.
+fn hash_list(hash: &mut Sha256, mut list: *const *const c_char) {
+    if list.is_null() {
+        hash.write(&[0]);
+        return;
+    }
+
+    hash.write(&[1]);
+
+    unsafe {
+        while !(*list).is_null() {
+            hash_string(hash, *list);
+            list = list.add(1);
+        }
+    }
+
+    hash.write(&[0]);
+}
+
 pub struct CompileResult {
     ptr: *mut c_char,
     len: usize,
@@ -95,25 +177,125 @@ impl CompileResult {
     pub fn bytecode(&self) -> Bytecode {
         Bytecode(unsafe { std::slice::from_raw_parts(self.ptr.cast(), self.len) })
     }
+
+    pub fn into_owned(self) -> BytecodeBuf {
+        BytecodeBuf(self.bytecode().0.to_vec())
+    }
 }
 
-pub struct Bytecode<'a>(&'a [u8]);
+#[derive(Debug)]
+pub enum BytecodeError {
+    Empty,
+    Version(u8),
+    Io(io::Error),
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytecodeError::Empty => write!(f, "bytecode is empty"),
+            BytecodeError::Version(version) => write!(
+                f,
+                "bytecode version {version} is not supported, expected \
+                 {} to {}",
+                sys::LBC_VERSION_MIN,
+                sys::LBC_VERSION_MAX,
+            ),
+            BytecodeError::Io(err) => write!(f, "failed to read bytecode: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BytecodeError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BytecodeError {
+    fn from(err: io::Error) -> Self {
+        BytecodeError::Io(err)
+    }
+}
+
+// Bytecode that owns its bytes, so it can be stored, sent between threads and
+// written to files. The version byte is checked against the versions the
+// linked Luau can load when it's created. A leading zero byte instead marks a
+// compile error, followed by its message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BytecodeBuf(Vec<u8>);
+
+impl BytecodeBuf {
+    pub fn new(bytes: Vec<u8>) -> Result<Self, BytecodeError> {
+        match bytes.first() {
+            None => Err(BytecodeError::Empty),
+            Some(0) => Ok(Self(bytes)),
+            Some(&version) if (sys::LBC_VERSION_MIN..=sys::LBC_VERSION_MAX).contains(&version) => {
+                Ok(Self(bytes))
+            }
+            Some(&version) => Err(BytecodeError::Version(version)),
+        }
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, BytecodeError> {
+        Self::new(fs::read(path)?)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, &self.0)
+    }
+
+    pub fn bytecode(&self) -> Bytecode<'_> {
+        Bytecode(&self.0)
+    }
+
+    pub fn version(&self) -> u8 {
+        self.0[0]
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl TryFrom<Vec<u8>> for BytecodeBuf {
+    type Error = BytecodeError;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
+        Self::new(bytes)
+    }
+}
+
+pub struct Bytecode<'a>(pub(crate) &'a [u8]);
 
 impl Bytecode<'_> {
     pub fn ptr(&self) -> *const c_char {
         self.0.as_ptr().cast()
     }
 
+    pub fn as_slice(&self) -> &[u8] {
+        self.0
+    }
+
     #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         self.0.len()
     }
 
     pub fn result(&self) -> Result<(), &str> {
-        if self.0.first().copied().unwrap() == 0 {
-            Err(unsafe { std::str::from_utf8_unchecked(&self.0[1..]) })
-        } else {
-            Ok(())
+        match self.0.first() {
+            None => Err("bytecode is empty"),
+            Some(0) => {
+                Err(std::str::from_utf8(&self.0[1..]).unwrap_or("compile error is not valid UTF-8"))
+            }
+            Some(_) => Ok(()),
         }
     }
 }
@@ -1,15 +1,15 @@
 use std::{
-    cell::RefCell,
+    cell::{Ref, RefCell, RefMut},
     ffi::{CStr, CString},
     marker::PhantomData,
     ops::Deref,
     ptr::NonNull,
 };
 
-use crate::{Config, Stack, ThreadRef, Type, Userdata};
+use crate::{Config, FromLuau, Stack, ThreadRef, Type, Userdata, userdata::short_type_name};
 
 #[repr(transparent)]
-pub struct FnReturn(i32);
+pub struct FnReturn(pub(crate) i32);
 
 #[repr(transparent)]
 pub struct Context<C: Config>(NonNull<sys::lua_State>, PhantomData<C>);
@@ -31,6 +31,14 @@ impl<C: Config> Context<C> {
         unsafe { sys::luaL_typeerror(self.as_ptr(), narg as _, type_name.as_ptr()) }
     }
 
+    pub fn arg<T: FromLuau>(&self, narg: u32) -> T {
+        T::from_luau(self, narg as _).unwrap_or_else(|| {
+            let type_name = CString::new(T::luau_type()).expect("type name contains null byte");
+
+            self.arg_type_error(narg, type_name.as_c_str())
+        })
+    }
+
     pub fn arg_boolean(&self, narg: u32) -> bool {
         self.to_boolean(narg as _)
             .unwrap_or_else(|| self.arg_type_error(narg, c"boolean"))
@@ -138,6 +146,57 @@ impl<C: Config> Context<C> {
         })
     }
 
+    // Also accepts types that extend `T`.
+    pub fn arg_userdata_ref<T: Userdata>(&self, narg: u32) -> Ref<'_, T> {
+        let borrow = match self.to_userdata::<T>(narg as _) {
+            Some(ud) => Some(ud.try_borrow()),
+            None => self.borrow_cast::<T>(narg as _),
+        };
+
+        self.borrowed(narg, borrow.map(|borrow| borrow.ok()), T::name())
+    }
+
+    pub fn arg_userdata_mut<T: Userdata>(&self, narg: u32) -> RefMut<'_, T> {
+        let borrow = match self.to_userdata::<T>(narg as _) {
+            Some(ud) => Some(ud.try_borrow_mut()),
+            None => self.borrow_mut_cast::<T>(narg as _),
+        };
+
+        self.borrowed(narg, borrow.map(|borrow| borrow.ok()), T::name())
+    }
+
+    pub fn arg_userdata_dyn<T: ?Sized + 'static>(&self, narg: u32) -> Ref<'_, T> {
+        let borrow = self.borrow_cast::<T>(narg as _);
+        let type_name = short_type_name(std::any::type_name::<T>());
+
+        self.borrowed(narg, borrow.map(|borrow| borrow.ok()), &type_name)
+    }
+
+    pub fn arg_userdata_dyn_mut<T: ?Sized + 'static>(&self, narg: u32) -> RefMut<'_, T> {
+        let borrow = self.borrow_mut_cast::<T>(narg as _);
+        let type_name = short_type_name(std::any::type_name::<T>());
+
+        self.borrowed(narg, borrow.map(|borrow| borrow.ok()), &type_name)
+    }
+
+    fn borrowed<B>(&self, narg: u32, borrow: Option<Option<B>>, type_name: &str) -> B {
+        match borrow {
+            Some(Some(borrow)) => borrow,
+            Some(None) => {
+                let actual = self.to_userdata_any(narg as _).unwrap_or(type_name);
+                let reason = CString::new(format!("{actual} is already borrowed"))
+                    .expect("userdata name contains null byte");
+
+                self.arg_error(narg, reason.as_c_str())
+            }
+            None => {
+                let type_name = CString::new(type_name).expect("userdata name contains null byte");
+
+                self.arg_type_error(narg, type_name.as_c_str())
+            }
+        }
+    }
+
     pub fn arg_userdata_opt<T: Userdata>(&self, narg: u32) -> Option<&RefCell<T>> {
         fn error<C: Config, T: Userdata>(ctx: &Context<C>, narg: u32) -> ! {
             let type_name = CString::new(format!("{} or nil", T::name()))
@@ -0,0 +1,232 @@
+use std::{collections::HashMap, ffi::c_void, fmt, ops::ControlFlow};
+
+use crate::{Config, Ref, Stack, Type, Userdata};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CopyPolicy {
+    #[default]
+    Error,
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyError {
+    Unsupported(Type),
+    // The userdata type has a clone but isn't registered in the destination.
+    Unregistered(&'static str),
+    // The destination is the stack the value is on.
+    SameStack,
+}
+
+impl fmt::Display for CopyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CopyError::Unsupported(ty) => write!(f, "cannot copy a value of type {ty:?}"),
+            CopyError::Unregistered(name) => {
+                write!(
+                    f,
+                    "userdata type {name} is not registered in the destination"
+                )
+            }
+            CopyError::SameStack => write!(f, "cannot copy a value to the stack it's on"),
+        }
+    }
+}
+
+impl std::error::Error for CopyError {}
+
+// Returns false if the value isn't of the clone's type.
+type CloneFn<C> = fn(from: &Stack<C>, idx: i32, to: &Stack<C>) -> Result<bool, CopyError>;
+
+// Threads are copied according to the function policy.
+pub struct CopyOptions<C: Config> {
+    functions: CopyPolicy,
+    userdata: CopyPolicy,
+    clones: HashMap<u32, CloneFn<C>>,
+}
+
+impl<C: Config> Default for CopyOptions<C> {
+    fn default() -> Self {
+        Self {
+            functions: CopyPolicy::default(),
+            userdata: CopyPolicy::default(),
+            clones: HashMap::new(),
+        }
+    }
+}
+
+impl<C: Config> CopyOptions<C> {
+    pub fn with_functions(mut self, policy: CopyPolicy) -> Self {
+        self.functions = policy;
+        self
+    }
+
+    pub fn with_userdata(mut self, policy: CopyPolicy) -> Self {
+        self.userdata = policy;
+        self
+    }
+
+    pub fn with_userdata_clone<U: Userdata + Clone>(mut self) -> Self {
+        fn clone<C: Config, U: Userdata + Clone>(
+            from: &Stack<C>,
+            idx: i32,
+            to: &Stack<C>,
+        ) -> Result<bool, CopyError> {
+            match from.to_userdata::<U>(idx) {
+                Some(value) => {
+                    to.reserve(1);
+                    to.try_push_userdata(value.borrow().clone())
+                        .map_err(|_| CopyError::Unregistered(U::name()))?;
+
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        }
+
+        self.clones.insert(U::tag(), clone::<C, U>);
+        self
+    }
+}
+
+struct Copier<'a, C: Config> {
+    from: &'a Stack<C>,
+    to: &'a Stack<C>,
+    options: &'a CopyOptions<C>,
+    tables: HashMap<*const c_void, Ref<C>>,
+}
+
+impl<C: Config> Copier<'_, C> {
+    fn policy(&self, policy: CopyPolicy, ty: Type) -> Result<bool, CopyError> {
+        match policy {
+            CopyPolicy::Error => Err(CopyError::Unsupported(ty)),
+            CopyPolicy::Skip => Ok(false),
+        }
+    }
+
+    // Pushes a copy of the value onto the destination stack, returning false
+    // if the value was skipped and nothing was pushed.
+    fn copy(&mut self, idx: i32) -> Result<bool, CopyError> {
+        let (from, to) = (self.from, self.to);
+        to.reserve(1);
+
+        match from.type_of(idx) {
+            Type::None | Type::Nil => to.push_nil(),
+            Type::Boolean => to.push_boolean(from.to_boolean_unchecked(idx)),
+            Type::LightUserdata => unsafe {
+                let tag = sys::lua_lightuserdatatag(from.as_ptr(), idx);
+                let ptr = sys::lua_tolightuserdatatagged(from.as_ptr(), idx, tag);
+                sys::lua_pushlightuserdatatagged(to.as_ptr(), ptr, tag);
+            },
+            Type::Number => to.push_number(from.to_number_unchecked(idx)),
+            Type::Vector => to.push_vector(unsafe { from.to_vector_unchecked(idx) }),
+            Type::String => to.push_string(unsafe { from.to_string_slice_unchecked(idx) }),
+            Type::Buffer => unsafe {
+                let (src, len) = from.to_buffer_unchecked(idx);
+                let (dst, _) = to.push_buffer(len);
+                std::ptr::copy_nonoverlapping(src, dst, len);
+            },
+            Type::Table => return self.copy_table(idx),
+            Type::Userdata => {
+                let tag = from.userdata_tag(idx);
+
+                if let Some(clone) = tag.and_then(|tag| self.options.clones.get(&tag))
+                    && clone(from, idx, to)?
+                {
+                    return Ok(true);
+                }
+
+                return self.policy(self.options.userdata, Type::Userdata);
+            }
+            ty @ (Type::Function | Type::Thread) => return self.policy(self.options.functions, ty),
+        }
+
+        Ok(true)
+    }
+
+    fn copy_table(&mut self, idx: i32) -> Result<bool, CopyError> {
+        let (from, to) = (self.from, self.to);
+        let ptr = unsafe { sys::lua_topointer(from.as_ptr(), idx) };
+
+        if let Some(table) = self.tables.get(&ptr) {
+            to.push_ref(table);
+            return Ok(true);
+        }
+
+        to.reserve(3);
+        to.push_table();
+        self.tables.insert(ptr, to.to_ref(-1));
+
+        from.reserve(2);
+        let top = from.get_top();
+
+        let error = from.iter(idx, || {
+            let entry = self.copy(top + 1).and_then(|key| {
+                if !key {
+                    return Ok(());
+                }
+
+                if self.copy(top + 2)? {
+                    to.table_set_raw(-3);
+                } else {
+                    to.pop(1);
+                }
+
+                Ok(())
+            });
+
+            match entry {
+                Ok(()) => ControlFlow::Continue(()),
+                Err(err) => ControlFlow::Break(err),
+            }
+        });
+
+        if let Some(err) = error {
+            from.set_top(top);
+            return Err(err);
+        }
+
+        to.table_set_readonly(-1, from.table_get_readonly(idx));
+        Ok(true)
+    }
+}
+
+impl<C: Config> Stack<C> {
+    // `to` must be another stack, whether of another `State` or another
+    // thread of this one, since copies are built up on top of it.
+    pub fn copy_to(&self, to: &Stack<C>, idx: i32) -> Result<(), CopyError> {
+        self.copy_to_with(to, idx, &CopyOptions::default())
+    }
+
+    pub fn copy_to_with(
+        &self,
+        to: &Stack<C>,
+        idx: i32,
+        options: &CopyOptions<C>,
+    ) -> Result<(), CopyError> {
+        if self.as_ptr() == to.as_ptr() {
+            return Err(CopyError::SameStack);
+        }
+
+        let top = to.get_top();
+
+        let mut copier = Copier {
+            from: self,
+            to,
+            options,
+            tables: HashMap::new(),
+        };
+
+        match copier.copy(self.abs_idx(idx)) {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                to.push_nil();
+                Ok(())
+            }
+            Err(err) => {
+                to.set_top(top);
+                Err(err)
+            }
+        }
+    }
+}
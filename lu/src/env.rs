@@ -0,0 +1,53 @@
+use crate::{Config, Ref, Stack};
+
+// A table of globals for a set of scripts. Reads fall back to the globals it
+// was created from, and writes either stay in the table or go through to
+// those globals.
+pub struct Environment<C: Config>(Ref<C>);
+
+impl<C: Config> Environment<C> {
+    pub fn new(stack: &Stack<C>) -> Self {
+        Self::create(stack, false)
+    }
+
+    pub fn new_write_through(stack: &Stack<C>) -> Self {
+        Self::create(stack, true)
+    }
+
+    fn create(stack: &Stack<C>, write_through: bool) -> Self {
+        stack.reserve(3);
+        stack.push_table();
+
+        stack.push_table();
+        stack.push_copy(sys::LUA_GLOBALSINDEX);
+        stack.table_set_raw_field(-2, c"__index");
+
+        if write_through {
+            stack.push_copy(sys::LUA_GLOBALSINDEX);
+            stack.table_set_raw_field(-2, c"__newindex");
+        }
+
+        stack.table_set_readonly(-1, true);
+        unsafe { sys::lua_setmetatable(stack.as_ptr(), -2) };
+
+        // Like `Thread::sandbox`, imports can be resolved at load time as
+        // long as the globals underneath can't change.
+        if stack.table_get_readonly(sys::LUA_GLOBALSINDEX) {
+            stack.set_safeenv(-1, true);
+        }
+
+        let env = stack.to_ref(-1);
+        stack.pop(1);
+
+        Self(env)
+    }
+
+    pub fn push(&self, stack: &Stack<C>) {
+        stack.reserve(1);
+        stack.push_ref(&self.0);
+    }
+
+    pub fn table(&self) -> &Ref<C> {
+        &self.0
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{Config, Context, FnReturn, ThreadMain};
+use crate::{Config, Context, FnReturn, ThreadMain, state::Shared};
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -40,11 +40,18 @@ impl From<sys::lua_Type> for Type {
     }
 }
 
-pub struct Ref<C: Config>(pub(crate) ThreadMain<C>, pub(crate) u32);
+pub struct Ref<C: Config>(pub(crate) ThreadMain<C>, pub(crate) u32, pub(crate) u64);
 
 impl<C: Config> Drop for Ref<C> {
     fn drop(&mut self) {
-        unsafe { sys::lua_unref(self.0.as_ptr(), self.1 as _) }
+        unsafe {
+            Shared::get(self.0.as_ptr())
+                .refs
+                .borrow_mut()
+                .remove(&self.2);
+
+            sys::lua_unref(self.0.as_ptr(), self.1 as _)
+        }
     }
 }
 
@@ -84,3 +91,70 @@ impl<C: Config> Function<C> {
         }
     }
 }
+
+// A function along with its Luau type, e.g. `(a: number, b: number?) -> number`.
+pub struct TypedFunction<C: Config> {
+    function: Function<C>,
+    signature: String,
+}
+
+impl<C: Config> TypedFunction<C> {
+    pub fn new(function: Function<C>, signature: impl Into<String>) -> Self {
+        Self {
+            function,
+            signature: signature.into(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.function.name()
+    }
+
+    pub fn function(&self) -> &Function<C> {
+        &self.function
+    }
+
+    pub fn signature(&self) -> &str {
+        &self.signature
+    }
+
+    pub fn into_parts(self) -> (Function<C>, String) {
+        (self.function, self.signature)
+    }
+}
+
+// Functions without a signature take and return anything.
+pub(crate) fn luau_function(signature: Option<&str>) -> &str {
+    signature.unwrap_or("(...any) -> ...any")
+}
+
+// Method signatures leave out `self`, which is added back here.
+pub(crate) fn luau_method(name: &str, signature: Option<&str>) -> String {
+    let (params, ret) = split_signature(luau_function(signature)).unwrap_or(("...any", "...any"));
+
+    if params.is_empty() {
+        format!("function {name}(self): {ret}")
+    } else {
+        format!("function {name}(self, {params}): {ret}")
+    }
+}
+
+// Splits `(a: number) -> number` into its parameters and return type.
+fn split_signature(signature: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+
+    for (i, c) in signature.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => continue,
+        }
+
+        if depth == 0 {
+            let ret = signature[i + 1..].strip_prefix(" -> ")?;
+            return Some((signature.get(1..i)?, ret));
+        }
+    }
+
+    None
+}
@@ -1,25 +1,42 @@
-pub use derive::Userdata;
+pub use derive::{LightUserdata, Userdata, function, library, methods};
 pub use sys;
 
+pub mod actor;
+pub mod libs;
+#[cfg(feature = "serde")]
+pub mod serde;
+
 mod alloc;
+mod atom;
+mod cache;
 mod compiler;
 mod context;
+mod copy;
+mod env;
 mod extra;
 mod library;
 mod stack;
 mod state;
 mod thread;
 mod userdata;
+mod value;
 
 pub use alloc::{DefaultAllocator, LuauAllocator};
-pub use compiler::{Bytecode, CompileResult, Compiler};
+pub use cache::BytecodeCache;
+pub use compiler::{Bytecode, BytecodeBuf, BytecodeError, CompileResult, Compiler};
 pub use context::{Context, FnReturn};
-pub use extra::{Function, Ref, Status, Type};
-pub use library::{Library, LibraryConstant, LibraryItem};
+pub use copy::{CopyError, CopyOptions, CopyPolicy};
+pub use env::Environment;
+pub use extra::{Function, Ref, Status, Type, TypedFunction};
+pub use library::{ConstantKeyError, ConstantTable, Library, LibraryConstant, LibraryItem};
 pub use stack::Stack;
-pub use state::State;
+pub use state::{CheckpointError, SendableState, State};
 pub use thread::{Thread, ThreadMain, ThreadRef};
-pub use userdata::{Methods, Userdata, unique_tag};
+pub use userdata::{
+    LightUserdata, Methods, RegisterError, UnregisteredUserdata, Userdata, UserdataInfo,
+    UserdataKind, generic_name_for, unique_light_tag, unique_tag, unique_tag_for,
+};
+pub use value::{FromLuau, IntoLuau, Multi, Returns};
 
 #[allow(unused)]
 pub trait Config: Sized {
@@ -1,14 +1,42 @@
-use crate::{Config, Context, FnReturn, Function, Stack, Status};
+use std::{collections::HashMap, ffi::c_void, fmt};
 
-#[derive(Default)]
-pub struct Library<C: Config>(Vec<(&'static str, LibraryItem<C>)>);
+use crate::{
+    Config, Context, FnReturn, Function, Stack, Status, TypedFunction, extra::luau_function,
+};
+
+pub struct Library<C: Config> {
+    items: Vec<(&'static str, LibraryItem<C>)>,
+    signatures: HashMap<&'static str, String>,
+    frozen: bool,
+}
+
+impl<C: Config> Default for Library<C> {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            signatures: HashMap::new(),
+            frozen: false,
+        }
+    }
+}
 
 impl<C: Config> Library<C> {
     pub fn with(mut self, name: &'static str, item: impl Into<LibraryItem<C>>) -> Self {
-        self.0.push((name, item.into()));
+        self.items.push((name, item.into()));
         self
     }
 
+    // Frozen libraries, and all of their sub-libraries, are pushed as
+    // read-only tables.
+    pub fn frozen(mut self, frozen: bool) -> Self {
+        self.frozen = frozen;
+        self
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
     pub fn with_constant(self, name: &'static str, constant: impl Into<LibraryConstant>) -> Self {
         self.with(name, LibraryItem::Constant(constant.into()))
     }
@@ -17,6 +45,13 @@ impl<C: Config> Library<C> {
         self.with(name, LibraryItem::Function(func))
     }
 
+    // Like `with_function`, but the signature is used for type definitions.
+    pub fn with_typed_function(mut self, name: &'static str, func: TypedFunction<C>) -> Self {
+        let (func, signature) = func.into_parts();
+        self.signatures.insert(name, signature);
+        self.with_function(name, func)
+    }
+
     pub fn with_function_norm(
         self,
         name: &'static str,
@@ -34,15 +69,75 @@ impl<C: Config> Library<C> {
         self.with(name, Function::cont(name, func, cont))
     }
 
+    // Emits a luau-lsp declaration for the library as a global named `name`.
+    pub fn type_definitions(&self, name: &str) -> String {
+        format!("declare {name}: {}\n", self.luau_type(0))
+    }
+
+    fn luau_type(&self, depth: usize) -> String {
+        if self.items.is_empty() {
+            return "{}".into();
+        }
+
+        let indent = "    ".repeat(depth + 1);
+        let mut ty = String::from("{\n");
+
+        for (name, item) in &self.items {
+            let item = match item {
+                LibraryItem::Library(lib) => lib.luau_type(depth + 1),
+                LibraryItem::Constant(constant) => constant.luau_type(),
+                LibraryItem::Function(_) => {
+                    luau_function(self.signatures.get(name).map(String::as_str)).into()
+                }
+            };
+
+            ty.push_str(&format!("{indent}{}: {item},\n", luau_key(name)));
+        }
+
+        ty.push_str(&"    ".repeat(depth));
+        ty.push('}');
+        ty
+    }
+
     pub fn push(&self, stack: &Stack<C>) {
+        self.push_frozen(stack, false);
+    }
+
+    pub(crate) fn push_frozen(&self, stack: &Stack<C>, frozen: bool) {
+        let frozen = frozen || self.frozen;
+
         stack.reserve(2);
         stack.push_table();
 
-        for (name, item) in &self.0 {
+        for (name, item) in &self.items {
             stack.push_string(name);
-            item.push(stack);
+
+            match item {
+                LibraryItem::Library(lib) => lib.push_frozen(stack, frozen),
+                item => item.push(stack),
+            }
+
             stack.table_set_raw(-3);
         }
+
+        if frozen {
+            stack.table_set_readonly(-1, true);
+        }
+    }
+}
+
+// Names that aren't identifiers are written as string keys.
+fn luau_key(name: &str) -> String {
+    let mut chars = name.chars();
+    let ident = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if ident {
+        name.into()
+    } else {
+        format!("[{name:?}]")
     }
 }
 
@@ -84,45 +179,204 @@ impl<C: Config> LibraryItem<C> {
 }
 
 pub enum LibraryConstant {
+    Nil,
     Bool(bool),
     Number(f64),
     String(&'static str),
+    OwnedString(String),
     Vector(f32, f32, f32),
+    Buffer(Vec<u8>),
+    // An untagged light userdata, for sentinels compared by address.
+    LightUserdata(*mut c_void),
+    // Pushed as a read-only table.
+    Table(ConstantTable),
 }
 
+// The entries of a `LibraryConstant::Table`, checked to be valid table keys
+// when it's built so that pushing it can't fail.
+pub struct ConstantTable(Vec<(LibraryConstant, LibraryConstant)>);
+
+impl ConstantTable {
+    pub fn new(entries: Vec<(LibraryConstant, LibraryConstant)>) -> Result<Self, ConstantKeyError> {
+        for (key, _) in &entries {
+            match key {
+                LibraryConstant::Nil => return Err(ConstantKeyError::Nil),
+                LibraryConstant::Number(n) if n.is_nan() => return Err(ConstantKeyError::NaN),
+                _ => {}
+            }
+        }
+
+        Ok(Self(entries))
+    }
+
+    pub fn entries(&self) -> &[(LibraryConstant, LibraryConstant)] {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstantKeyError {
+    Nil,
+    NaN,
+}
+
+impl fmt::Display for ConstantKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConstantKeyError::Nil => write!(f, "library constant table has a nil key"),
+            ConstantKeyError::NaN => write!(f, "library constant table has a NaN key"),
+        }
+    }
+}
+
+impl std::error::Error for ConstantKeyError {}
+
 impl LibraryConstant {
+    pub fn luau_type(&self) -> String {
+        match self {
+            LibraryConstant::Nil => "nil".into(),
+            LibraryConstant::Bool(_) => "boolean".into(),
+            LibraryConstant::Number(_) => "number".into(),
+            LibraryConstant::String(_) | LibraryConstant::OwnedString(_) => "string".into(),
+            LibraryConstant::Vector(..) => "vector".into(),
+            LibraryConstant::Buffer(_) => "buffer".into(),
+            LibraryConstant::LightUserdata(_) => "any".into(),
+            LibraryConstant::Table(table) => table_type(table.entries()),
+        }
+    }
+
     pub fn push<C: Config>(&self, stack: &Stack<C>) {
         stack.reserve(1);
 
         match self {
+            LibraryConstant::Nil => stack.push_nil(),
             LibraryConstant::Bool(value) => stack.push_boolean(*value),
             LibraryConstant::Number(value) => stack.push_number(*value),
             LibraryConstant::String(value) => stack.push_string(value),
+            LibraryConstant::OwnedString(value) => stack.push_string(value),
             LibraryConstant::Vector(x, y, z) => stack.push_vector((*x, *y, *z)),
+            LibraryConstant::Buffer(value) => {
+                let (ptr, len) = stack.push_buffer(value.len());
+                unsafe { std::ptr::copy_nonoverlapping(value.as_ptr(), ptr, len) };
+            }
+            LibraryConstant::LightUserdata(value) => stack.push_light_userdata(*value),
+            LibraryConstant::Table(table) => {
+                stack.push_table();
+
+                for (key, value) in table.entries() {
+                    key.push(stack);
+                    value.push(stack);
+                    stack.table_set_raw(-3);
+                }
+
+                stack.table_set_readonly(-1, true);
+            }
         }
     }
 }
 
+// String keys become properties, and the rest share a single indexer, which
+// is `any` when their types differ.
+fn table_type(entries: &[(LibraryConstant, LibraryConstant)]) -> String {
+    let mut props = Vec::new();
+    let mut indexer: Option<(String, String)> = None;
+
+    for (key, value) in entries {
+        let value = value.luau_type();
+
+        match key {
+            LibraryConstant::String(key) => props.push(format!("{}: {value}", luau_key(key))),
+            LibraryConstant::OwnedString(key) => props.push(format!("{}: {value}", luau_key(key))),
+            key => {
+                let key = key.luau_type();
+
+                indexer = Some(match indexer {
+                    None => (key, value),
+                    Some((k, v)) => (
+                        if k == key { k } else { "any".into() },
+                        if v == value { v } else { "any".into() },
+                    ),
+                });
+            }
+        }
+    }
+
+    if let Some((key, value)) = indexer {
+        props.push(format!("[{key}]: {value}"));
+    }
+
+    if props.is_empty() {
+        "{}".into()
+    } else {
+        format!("{{ {} }}", props.join(", "))
+    }
+}
+
 impl From<bool> for LibraryConstant {
     fn from(value: bool) -> Self {
         LibraryConstant::Bool(value)
     }
 }
 
-impl From<f64> for LibraryConstant {
-    fn from(value: f64) -> Self {
-        LibraryConstant::Number(value)
-    }
+// Integers past 2^53 can't be represented exactly, and are rounded to the
+// nearest number like any other conversion to `f64`.
+macro_rules! number {
+    ($($ty:ty),*) => {$(
+        impl From<$ty> for LibraryConstant {
+            fn from(value: $ty) -> Self {
+                LibraryConstant::Number(value as _)
+            }
+        }
+    )*};
 }
 
+number!(f32, f64, i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
 impl From<&'static str> for LibraryConstant {
     fn from(value: &'static str) -> Self {
         LibraryConstant::String(value)
     }
 }
 
+impl From<String> for LibraryConstant {
+    fn from(value: String) -> Self {
+        LibraryConstant::OwnedString(value)
+    }
+}
+
 impl From<(f32, f32, f32)> for LibraryConstant {
     fn from(value: (f32, f32, f32)) -> Self {
         LibraryConstant::Vector(value.0, value.1, value.2)
     }
 }
+
+impl From<Vec<u8>> for LibraryConstant {
+    fn from(value: Vec<u8>) -> Self {
+        LibraryConstant::Buffer(value)
+    }
+}
+
+impl From<ConstantTable> for LibraryConstant {
+    fn from(table: ConstantTable) -> Self {
+        LibraryConstant::Table(table)
+    }
+}
+
+impl<K: Into<LibraryConstant>, V: Into<LibraryConstant>> TryFrom<Vec<(K, V)>> for LibraryConstant {
+    type Error = ConstantKeyError;
+
+    fn try_from(entries: Vec<(K, V)>) -> Result<Self, ConstantKeyError> {
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| (key.into(), value.into()))
+            .collect();
+
+        ConstantTable::new(entries).map(LibraryConstant::Table)
+    }
+}
+
+impl<T: Into<LibraryConstant>> From<Option<T>> for LibraryConstant {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(LibraryConstant::Nil, Into::into)
+    }
+}
@@ -0,0 +1,712 @@
+use std::{
+    collections::HashSet,
+    ffi::{CString, c_void},
+    fmt::Write,
+    ops::ControlFlow,
+};
+
+use crate::{Config, Context, FnReturn, Library, LibraryConstant, Stack, Type};
+
+const MAX_DEPTH: usize = 256;
+
+// `json.null` is a unique light userdata so that `null` can be stored in
+// tables, where `nil` would leave a hole.
+static NULL: u8 = 0;
+
+fn null_ptr() -> *mut c_void {
+    &NULL as *const u8 as *mut c_void
+}
+
+fn push_null<C: Config>(stack: &Stack<C>) {
+    stack.push_light_userdata(null_ptr());
+}
+
+pub fn library<C: Config>() -> Library<C> {
+    Library::default()
+        .with_function_norm("encode", encode)
+        .with_function_norm("decode", decode)
+        .with_constant("null", LibraryConstant::LightUserdata(null_ptr()))
+}
+
+enum Segment {
+    Key(String),
+    Index(u32),
+}
+
+struct Encoder<'a, C: Config> {
+    stack: &'a Stack<C>,
+    pretty: bool,
+    sort_keys: bool,
+    path: Vec<Segment>,
+    tables: Vec<*const c_void>,
+}
+
+impl<C: Config> Encoder<'_, C> {
+    fn error(&self, reason: impl std::fmt::Display) -> String {
+        let mut path = String::from("$");
+
+        for segment in &self.path {
+            match segment {
+                Segment::Key(key) if is_identifier(key) => write!(path, ".{key}").unwrap(),
+                Segment::Key(key) => {
+                    path.push('[');
+                    encode_string(&mut path, key);
+                    path.push(']');
+                }
+                Segment::Index(index) => write!(path, "[{index}]").unwrap(),
+            }
+        }
+
+        format!("{reason} at {path}")
+    }
+
+    fn newline(&self, out: &mut String, depth: usize) {
+        if self.pretty {
+            out.push('\n');
+            out.extend(std::iter::repeat_n("  ", depth));
+        }
+    }
+
+    fn encode(&mut self, out: &mut String, idx: i32, depth: usize) -> Result<(), String> {
+        let stack = self.stack;
+
+        match stack.type_of(idx) {
+            Type::None | Type::Nil => out.push_str("null"),
+            Type::LightUserdata if stack.to_light_userdata_unchecked(idx) == null_ptr() => {
+                out.push_str("null")
+            }
+            Type::Boolean => {
+                out.push_str(match stack.to_boolean_unchecked(idx) {
+                    true => "true",
+                    false => "false",
+                });
+            }
+            Type::Number => self.encode_number(out, stack.to_number_unchecked(idx))?,
+            Type::Vector => {
+                let (x, y, z) = unsafe { stack.to_vector_unchecked(idx) };
+
+                out.push('[');
+                for (i, component) in [x, y, z].into_iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+
+                    self.encode_number(out, component as _)?;
+                }
+                out.push(']');
+            }
+            Type::String => {
+                let bytes = unsafe { stack.to_string_slice_unchecked(idx) };
+                let s = std::str::from_utf8(bytes)
+                    .map_err(|_| self.error("cannot encode a string that isn't valid utf-8"))?;
+
+                encode_string(out, s);
+            }
+            Type::Buffer => {
+                let (ptr, len) = unsafe { stack.to_buffer_unchecked(idx) };
+                let bytes = unsafe { std::slice::from_raw_parts(ptr, len) };
+                let s = std::str::from_utf8(bytes)
+                    .map_err(|_| self.error("cannot encode a buffer that isn't valid utf-8"))?;
+
+                encode_string(out, s);
+            }
+            Type::Table => self.encode_table(out, idx, depth)?,
+            ty => {
+                let name = format!("{ty:?}").to_lowercase();
+                return Err(self.error(format!("cannot encode a {name}")));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn encode_number(&self, out: &mut String, n: f64) -> Result<(), String> {
+        if !n.is_finite() {
+            return Err(self.error("cannot encode NaN or infinity"));
+        }
+
+        if n.fract() == 0.0 && n.abs() < 2f64.powi(53) {
+            write!(out, "{}", n as i64).unwrap();
+        } else {
+            write!(out, "{n:?}").unwrap();
+        }
+
+        Ok(())
+    }
+
+    fn encode_table(&mut self, out: &mut String, idx: i32, depth: usize) -> Result<(), String> {
+        let stack = self.stack;
+
+        let ptr = unsafe { sys::lua_topointer(stack.as_ptr(), idx) };
+        if self.tables.contains(&ptr) {
+            return Err(self.error("cannot encode a table that contains itself"));
+        }
+
+        if self.tables.len() >= MAX_DEPTH {
+            return Err(self.error("cannot encode a table nested this deeply"));
+        }
+
+        self.tables.push(ptr);
+        stack.reserve(2);
+
+        // Tables are arrays when their keys are exactly `1..=len`, anything
+        // else is encoded as an object so that no entries are dropped.
+        let len = stack.len(idx);
+        let top = stack.get_top();
+        let mut count = 0;
+        let array = stack
+            .iter(idx, || {
+                count += 1;
+
+                match stack.type_of(-2) {
+                    Type::Number => {
+                        let key = stack.to_number_unchecked(-2);
+
+                        if key.fract() == 0.0 && key >= 1.0 && key <= len as f64 {
+                            ControlFlow::Continue(())
+                        } else {
+                            ControlFlow::Break(())
+                        }
+                    }
+                    _ => ControlFlow::Break(()),
+                }
+            })
+            .is_none()
+            && count == len;
+
+        stack.set_top(top);
+
+        if array {
+            self.encode_array(out, idx, len, depth)?;
+        } else {
+            self.encode_object(out, idx, depth)?;
+        }
+
+        self.tables.pop();
+        Ok(())
+    }
+
+    fn encode_array(
+        &mut self,
+        out: &mut String,
+        idx: i32,
+        len: u32,
+        depth: usize,
+    ) -> Result<(), String> {
+        let stack = self.stack;
+
+        out.push('[');
+
+        for i in 1..=len {
+            if i > 1 {
+                out.push(',');
+            }
+
+            self.newline(out, depth + 1);
+            self.path.push(Segment::Index(i));
+
+            stack.table_get_raw_i(idx, i);
+            self.encode(out, stack.get_top(), depth + 1)?;
+            stack.pop(1);
+
+            self.path.pop();
+        }
+
+        if len > 0 {
+            self.newline(out, depth);
+        }
+
+        out.push(']');
+        Ok(())
+    }
+
+    fn encode_object(&mut self, out: &mut String, idx: i32, depth: usize) -> Result<(), String> {
+        let stack = self.stack;
+
+        let top = stack.get_top();
+        let mut entries = Vec::new();
+        let mut keys = HashSet::new();
+
+        let error = stack.iter(idx, || {
+            let key = match stack.type_of(top + 1) {
+                Type::String => match stack.to_string_str(top + 1) {
+                    Some(key) => key.to_owned(),
+                    None => {
+                        return ControlFlow::Break(
+                            self.error("cannot encode a key that isn't valid utf-8"),
+                        );
+                    }
+                },
+                Type::Number => {
+                    let n = stack.to_number_unchecked(top + 1);
+
+                    if n.fract() != 0.0 {
+                        return ControlFlow::Break(
+                            self.error(format!("cannot encode non-integer key {n}")),
+                        );
+                    }
+
+                    // Past 2^53 keys are no longer exact, and distinct keys
+                    // could be written as the same digits.
+                    if n.abs() >= 2f64.powi(53) {
+                        return ControlFlow::Break(
+                            self.error(format!("cannot encode key {n} beyond 2^53")),
+                        );
+                    }
+
+                    format!("{}", n as i64)
+                }
+                ty => {
+                    let name = format!("{ty:?}").to_lowercase();
+                    return ControlFlow::Break(self.error(format!("cannot encode a {name} key")));
+                }
+            };
+
+            // Number keys are written as strings, so `[1]` and `["1"]` would
+            // both become `"1"`.
+            if !keys.insert(key.clone()) {
+                let mut quoted = String::new();
+                encode_string(&mut quoted, &key);
+
+                return ControlFlow::Break(
+                    self.error(format!("cannot encode key {quoted} more than once")),
+                );
+            }
+
+            self.path.push(Segment::Key(key));
+
+            let mut value = String::new();
+            if let Err(err) = self.encode(&mut value, top + 2, depth + 1) {
+                return ControlFlow::Break(err);
+            }
+
+            let Some(Segment::Key(key)) = self.path.pop() else {
+                unreachable!()
+            };
+
+            entries.push((key, value));
+            ControlFlow::Continue(())
+        });
+
+        if let Some(err) = error {
+            stack.set_top(top);
+            return Err(err);
+        }
+
+        if self.sort_keys {
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        }
+
+        out.push('{');
+
+        for (i, (key, value)) in entries.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+
+            self.newline(out, depth + 1);
+            encode_string(out, key);
+            out.push(':');
+
+            if self.pretty {
+                out.push(' ');
+            }
+
+            out.push_str(value);
+        }
+
+        if !entries.is_empty() {
+            self.newline(out, depth);
+        }
+
+        out.push('}');
+        Ok(())
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn encode_string(out: &mut String, s: &str) {
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+}
+
+// Keys in paths are escaped, so errors shouldn't hold NUL bytes, but any that
+// do are escaped too rather than losing the message.
+fn arg_error<C: Config>(ctx: &Context<C>, err: String) -> ! {
+    let reason = CString::new(err.replace('\0', "\\0")).unwrap();
+    ctx.arg_error(1, reason.as_c_str())
+}
+
+fn option<C: Config>(ctx: &Context<C>, field: &std::ffi::CStr) -> bool {
+    ctx.table_get_field(2, field);
+    let enabled = ctx.to_boolean(-1).unwrap_or(false);
+    ctx.pop(1);
+
+    enabled
+}
+
+extern "C-unwind" fn encode<C: Config>(ctx: Context<C>) -> FnReturn {
+    let (pretty, sort_keys) = match ctx.arg_table_opt(2) {
+        Some(()) => {
+            ctx.reserve(1);
+            (option(&ctx, c"pretty"), option(&ctx, c"sort_keys"))
+        }
+        None => (false, false),
+    };
+
+    if ctx.is_none(1) {
+        ctx.arg_error(1, c"value expected");
+    }
+
+    let mut encoder = Encoder {
+        stack: &ctx,
+        pretty,
+        sort_keys,
+        path: Vec::new(),
+        tables: Vec::new(),
+    };
+
+    let mut out = String::new();
+    let result = encoder.encode(&mut out, 1, 0);
+    drop(encoder);
+
+    if let Err(err) = result {
+        drop(out);
+        arg_error(&ctx, err);
+    }
+
+    ctx.push_string(out);
+    ctx.ret_with(1)
+}
+
+struct Decoder<'a, C: Config> {
+    stack: &'a Stack<C>,
+    input: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<C: Config> Decoder<'_, C> {
+    fn error(&self, reason: &str) -> String {
+        let consumed = &self.input[..self.pos.min(self.input.len())];
+        let line = consumed.iter().filter(|&&b| b == b'\n').count() + 1;
+        let column = consumed.iter().rev().take_while(|&&b| b != b'\n').count() + 1;
+
+        format!("{reason} at line {line}, column {column}")
+    }
+
+    fn unexpected(&self) -> String {
+        match self.peek() {
+            Some(b) if b.is_ascii_graphic() => {
+                self.error(&format!("unexpected character '{}'", b as char))
+            }
+            Some(b) => self.error(&format!("unexpected byte 0x{b:02x}")),
+            None => self.error("unexpected end of input"),
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &[u8]) -> Result<(), String> {
+        if self.input[self.pos..].starts_with(literal) {
+            self.pos += literal.len();
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn decode(&mut self) -> Result<(), String> {
+        self.skip_whitespace();
+        self.stack.reserve(1);
+
+        match self.peek() {
+            Some(b'n') => {
+                self.expect(b"null")?;
+                push_null(self.stack);
+            }
+            Some(b't') => {
+                self.expect(b"true")?;
+                self.stack.push_boolean(true);
+            }
+            Some(b'f') => {
+                self.expect(b"false")?;
+                self.stack.push_boolean(false);
+            }
+            Some(b'"') => {
+                let s = self.decode_string()?;
+                self.stack.push_string(s);
+            }
+            Some(b'-' | b'0'..=b'9') => {
+                let n = self.decode_number()?;
+                self.stack.push_number(n);
+            }
+            Some(b'[') => self.decode_array()?,
+            Some(b'{') => self.decode_object()?,
+            _ => return Err(self.unexpected()),
+        }
+
+        Ok(())
+    }
+
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+
+        if self.depth > MAX_DEPTH {
+            Err(self.error("value nested too deeply"))
+        } else {
+            self.stack.reserve(3);
+            Ok(())
+        }
+    }
+
+    fn decode_array(&mut self) -> Result<(), String> {
+        self.enter()?;
+        self.pos += 1;
+        self.stack.push_table();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            self.depth -= 1;
+            return Ok(());
+        }
+
+        let mut index = 0;
+        loop {
+            self.decode()?;
+
+            index += 1;
+            self.stack.table_set_raw_i(-2, index);
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn decode_object(&mut self) -> Result<(), String> {
+        self.enter()?;
+        self.pos += 1;
+        self.stack.push_table();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            self.depth -= 1;
+            return Ok(());
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.unexpected());
+            }
+
+            let key = self.decode_string()?;
+            self.stack.push_string(key);
+
+            self.skip_whitespace();
+            self.expect(b":")?;
+
+            self.decode()?;
+            self.stack.table_set_raw(-3);
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn decode_number(&mut self) -> Result<f64, String> {
+        let start = self.pos;
+
+        let digits = |decoder: &mut Self| {
+            let start = decoder.pos;
+            while let Some(b'0'..=b'9') = decoder.peek() {
+                decoder.pos += 1;
+            }
+
+            decoder.pos > start
+        };
+
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+
+        if self.peek() == Some(b'0') {
+            self.pos += 1;
+        } else if !digits(self) {
+            return Err(self.unexpected());
+        }
+
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+
+            if !digits(self) {
+                return Err(self.unexpected());
+            }
+        }
+
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+
+            if !digits(self) {
+                return Err(self.unexpected());
+            }
+        }
+
+        let text = std::str::from_utf8(&self.input[start..self.pos]).unwrap();
+        Ok(text.parse().unwrap())
+    }
+
+    fn decode_hex(&mut self) -> Result<u32, String> {
+        let hex = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+
+        self.pos += 4;
+        Ok(hex)
+    }
+
+    fn decode_string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut out = Vec::new();
+
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{08}',
+                        Some(b'f') => '\u{0c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.pos += 1;
+                            let mut code = self.decode_hex()?;
+
+                            if (0xd800..0xdc00).contains(&code) {
+                                self.expect(b"\\u")?;
+                                let low = self.decode_hex()?;
+
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error("invalid unicode surrogate pair"));
+                                }
+
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+
+                            self.pos -= 1;
+                            char::from_u32(code)
+                                .ok_or_else(|| self.error("invalid unicode escape"))?
+                        }
+                        _ => return Err(self.unexpected()),
+                    };
+
+                    self.pos += 1;
+                    out.extend_from_slice(escaped.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                Some(b) if b < 0x20 => return Err(self.error("control character in string")),
+                Some(b) => {
+                    self.pos += 1;
+                    out.push(b);
+                }
+                None => return Err(self.unexpected()),
+            }
+        }
+
+        String::from_utf8(out).map_err(|_| self.error("string is not valid utf-8"))
+    }
+}
+
+extern "C-unwind" fn decode<C: Config>(ctx: Context<C>) -> FnReturn {
+    let input = ctx.arg_string_slice(1);
+
+    let mut decoder = Decoder {
+        stack: &ctx,
+        input,
+        pos: 0,
+        depth: 0,
+    };
+
+    let result = decoder.decode().and_then(|()| {
+        decoder.skip_whitespace();
+
+        match decoder.peek() {
+            Some(_) => Err(decoder.unexpected()),
+            None => Ok(()),
+        }
+    });
+
+    if let Err(err) = result {
+        arg_error(&ctx, err);
+    }
+
+    ctx.ret_with(1)
+}
@@ -0,0 +1,1226 @@
+use std::{cmp::Ordering, fmt, ops::ControlFlow, vec};
+
+use ::serde::{
+    Serialize,
+    de::{self, DeserializeOwned, IntoDeserializer, Unexpected},
+    ser::{self, Impossible},
+};
+
+use crate::{Config, Stack, Type};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    detect_arrays: bool,
+    sort_keys: bool,
+    vectors_as_arrays: bool,
+    buffers_as_bytes: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            detect_arrays: true,
+            sort_keys: false,
+            vectors_as_arrays: true,
+            buffers_as_bytes: true,
+        }
+    }
+}
+
+impl Options {
+    // Tables with only the keys `1..=n` are deserialized as sequences when
+    // the target type doesn't say which it expects.
+    pub fn with_detect_arrays(mut self, enabled: bool) -> Self {
+        self.detect_arrays = enabled;
+        self
+    }
+
+    pub fn with_sort_keys(mut self, enabled: bool) -> Self {
+        self.sort_keys = enabled;
+        self
+    }
+
+    // Vectors are read as `[f32; 3]`, and tuples of three `f32`s are written
+    // as vectors. Otherwise vectors are read as `{ x, y, z }`.
+    pub fn with_vectors_as_arrays(mut self, enabled: bool) -> Self {
+        self.vectors_as_arrays = enabled;
+        self
+    }
+
+    // Byte slices are written as buffers rather than strings.
+    pub fn with_buffers_as_bytes(mut self, enabled: bool) -> Self {
+        self.buffers_as_bytes = enabled;
+        self
+    }
+}
+
+pub fn to_stack<C: Config, T: Serialize + ?Sized>(
+    stack: &Stack<C>,
+    value: &T,
+) -> Result<(), Error> {
+    to_stack_with(stack, value, &Options::default())
+}
+
+pub fn to_stack_with<C: Config, T: Serialize + ?Sized>(
+    stack: &Stack<C>,
+    value: &T,
+    options: &Options,
+) -> Result<(), Error> {
+    let top = stack.get_top();
+    let result = value.serialize(Serializer::new(stack, options));
+
+    if result.is_err() {
+        stack.set_top(top);
+    }
+
+    result
+}
+
+pub fn from_stack<C: Config, T: DeserializeOwned>(stack: &Stack<C>, idx: i32) -> Result<T, Error> {
+    from_stack_with(stack, idx, &Options::default())
+}
+
+pub fn from_stack_with<C: Config, T: DeserializeOwned>(
+    stack: &Stack<C>,
+    idx: i32,
+    options: &Options,
+) -> Result<T, Error> {
+    let top = stack.get_top();
+    let result = T::deserialize(Deserializer::new(stack, idx, options));
+    stack.set_top(top);
+
+    result
+}
+
+pub struct Serializer<'a, C: Config> {
+    stack: &'a Stack<C>,
+    options: &'a Options,
+}
+
+impl<C: Config> Clone for Serializer<'_, C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C: Config> Copy for Serializer<'_, C> {}
+
+impl<'a, C: Config> Serializer<'a, C> {
+    pub fn new(stack: &'a Stack<C>, options: &'a Options) -> Self {
+        Self { stack, options }
+    }
+
+    fn push_table(self, narr: usize, nrec: usize) -> TableSerializer<'a, C> {
+        self.stack.reserve(3);
+        self.stack.push_table_with(narr as _, nrec as _);
+
+        TableSerializer {
+            ser: self,
+            index: 0,
+        }
+    }
+
+    fn push_variant(self, variant: &str, narr: usize, nrec: usize) -> TableSerializer<'a, C> {
+        self.stack.reserve(2);
+        self.stack.push_table_with(0, 1);
+        self.stack.push_string(variant);
+
+        self.push_table(narr, nrec)
+    }
+}
+
+impl<'a, C: Config> ser::Serializer for Serializer<'a, C> {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = TableSerializer<'a, C>;
+    type SerializeTuple = TupleSerializer<'a, C>;
+    type SerializeTupleStruct = TableSerializer<'a, C>;
+    type SerializeTupleVariant = TableSerializer<'a, C>;
+    type SerializeMap = TableSerializer<'a, C>;
+    type SerializeStruct = TableSerializer<'a, C>;
+    type SerializeStructVariant = TableSerializer<'a, C>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.stack.reserve(1);
+        self.stack.push_boolean(v);
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), Error> {
+        self.serialize_f64(v as _)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), Error> {
+        self.serialize_f64(v as _)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), Error> {
+        self.serialize_f64(v as _)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), Error> {
+        self.serialize_f64(v as _)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Error> {
+        self.serialize_f64(v as _)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), Error> {
+        self.serialize_f64(v as _)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), Error> {
+        self.serialize_f64(v as _)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), Error> {
+        self.serialize_f64(v as _)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), Error> {
+        self.serialize_f64(v as _)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), Error> {
+        self.stack.reserve(1);
+        self.stack.push_number(v);
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), Error> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Error> {
+        self.stack.reserve(1);
+        self.stack.push_string(v);
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+        self.stack.reserve(1);
+
+        if self.options.buffers_as_bytes {
+            let (ptr, len) = self.stack.push_buffer(v.len());
+            unsafe { std::ptr::copy_nonoverlapping(v.as_ptr(), ptr, len) };
+        } else {
+            self.stack.push_string(v);
+        }
+
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        self.stack.reserve(1);
+        self.stack.push_nil();
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.stack.reserve(2);
+        self.stack.push_table_with(0, 1);
+        self.stack.push_string(variant);
+
+        value.serialize(self)?;
+        self.stack.table_set_raw(-3);
+        Ok(())
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<TableSerializer<'a, C>, Error> {
+        Ok(self.push_table(len.unwrap_or(0), 0))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<TupleSerializer<'a, C>, Error> {
+        if self.options.vectors_as_arrays && len == 3 {
+            Ok(TupleSerializer::Vector(self, Vec::with_capacity(3)))
+        } else {
+            Ok(TupleSerializer::Table(self.push_table(len, 0)))
+        }
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<TableSerializer<'a, C>, Error> {
+        Ok(self.push_table(len, 0))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<TableSerializer<'a, C>, Error> {
+        Ok(self.push_variant(variant, len, 0))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<TableSerializer<'a, C>, Error> {
+        Ok(self.push_table(0, len.unwrap_or(0)))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<TableSerializer<'a, C>, Error> {
+        Ok(self.push_table(0, len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<TableSerializer<'a, C>, Error> {
+        Ok(self.push_variant(variant, 0, len))
+    }
+}
+
+pub struct TableSerializer<'a, C: Config> {
+    ser: Serializer<'a, C>,
+    index: u32,
+}
+
+impl<C: Config> TableSerializer<'_, C> {
+    fn push_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        value.serialize(self.ser)?;
+
+        self.index += 1;
+        self.ser.stack.table_set_raw_i(-2, self.index);
+        Ok(())
+    }
+
+    fn push_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        key.serialize(self.ser)?;
+
+        let stack = self.ser.stack;
+        if stack.is_nil(-1) || stack.to_number(-1).is_some_and(f64::is_nan) {
+            return Err(Error("table key cannot be nil or NaN".into()));
+        }
+
+        Ok(())
+    }
+
+    fn push_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        value.serialize(self.ser)?;
+
+        self.ser.stack.table_set_raw(-3);
+        Ok(())
+    }
+
+    // Variants are written as `{ [variant] = table }`, with the outer table
+    // and the variant name below the table being built.
+    fn end_variant(self) -> Result<(), Error> {
+        self.ser.stack.table_set_raw(-3);
+        Ok(())
+    }
+}
+
+impl<C: Config> ser::SerializeSeq for TableSerializer<'_, C> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<C: Config> ser::SerializeTupleStruct for TableSerializer<'_, C> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<C: Config> ser::SerializeTupleVariant for TableSerializer<'_, C> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.end_variant()
+    }
+}
+
+impl<C: Config> ser::SerializeMap for TableSerializer<'_, C> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.push_key(key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push_value(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<C: Config> ser::SerializeStruct for TableSerializer<'_, C> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.push_key(key)?;
+        self.push_value(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<C: Config> ser::SerializeStructVariant for TableSerializer<'_, C> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.push_key(key)?;
+        self.push_value(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.end_variant()
+    }
+}
+
+// A three element tuple stays a vector for as long as every element is an
+// `f32`, and falls back to a table as soon as one isn't.
+pub enum TupleSerializer<'a, C: Config> {
+    Vector(Serializer<'a, C>, Vec<f32>),
+    Table(TableSerializer<'a, C>),
+}
+
+impl<C: Config> ser::SerializeTuple for TupleSerializer<'_, C> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        if let TupleSerializer::Vector(ser, components) = self {
+            if let Ok(component) = value.serialize(F32Serializer) {
+                components.push(component);
+                return Ok(());
+            }
+
+            let mut table = ser.push_table(3, 0);
+            for component in components.iter() {
+                table.push_element(component)?;
+            }
+
+            *self = TupleSerializer::Table(table);
+        }
+
+        match self {
+            TupleSerializer::Table(table) => table.push_element(value),
+            TupleSerializer::Vector(..) => unreachable!(),
+        }
+    }
+
+    fn end(self) -> Result<(), Error> {
+        match self {
+            TupleSerializer::Vector(ser, components) => match components[..] {
+                [x, y, z] => {
+                    ser.stack.reserve(1);
+                    ser.stack.push_vector((x, y, z));
+                    Ok(())
+                }
+                _ => Err(Error("expected three vector components".into())),
+            },
+            TupleSerializer::Table(_) => Ok(()),
+        }
+    }
+}
+
+struct F32Serializer;
+
+impl F32Serializer {
+    fn reject<T>(self) -> Result<T, Error> {
+        Err(Error("not an f32".into()))
+    }
+}
+
+impl ser::Serializer for F32Serializer {
+    type Ok = f32;
+    type Error = Error;
+
+    type SerializeSeq = Impossible<f32, Error>;
+    type SerializeTuple = Impossible<f32, Error>;
+    type SerializeTupleStruct = Impossible<f32, Error>;
+    type SerializeTupleVariant = Impossible<f32, Error>;
+    type SerializeMap = Impossible<f32, Error>;
+    type SerializeStruct = Impossible<f32, Error>;
+    type SerializeStructVariant = Impossible<f32, Error>;
+
+    fn serialize_f32(self, v: f32) -> Result<f32, Error> {
+        Ok(v)
+    }
+
+    fn serialize_bool(self, _: bool) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_i8(self, _: i8) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_i16(self, _: i16) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_i32(self, _: i32) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_i64(self, _: i64) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_u8(self, _: u8) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_u16(self, _: u16) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_u32(self, _: u32) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_u64(self, _: u64) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_f64(self, _: f64) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_char(self, _: char) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_str(self, _: &str) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_bytes(self, _: &[u8]) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_none(self) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _: &T) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_unit(self) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+    ) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<f32, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<f32, Error> {
+        self.reject()
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        self.reject()
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Error> {
+        self.reject()
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        self.reject()
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        self.reject()
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        self.reject()
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self::SerializeStruct, Error> {
+        self.reject()
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        self.reject()
+    }
+}
+
+pub struct Deserializer<'a, C: Config> {
+    stack: &'a Stack<C>,
+    idx: i32,
+    options: &'a Options,
+}
+
+impl<'a, C: Config> Deserializer<'a, C> {
+    pub fn new(stack: &'a Stack<C>, idx: i32, options: &'a Options) -> Self {
+        Self {
+            stack,
+            idx: stack.abs_idx(idx),
+            options,
+        }
+    }
+
+    fn at(&self, idx: i32) -> Self {
+        Self::new(self.stack, idx, self.options)
+    }
+
+    fn unexpected(&self) -> Unexpected<'a> {
+        let stack = self.stack;
+
+        match stack.type_of(self.idx) {
+            Type::None | Type::Nil => Unexpected::Unit,
+            Type::Boolean => Unexpected::Bool(stack.to_boolean_unchecked(self.idx)),
+            Type::Number => Unexpected::Float(stack.to_number_unchecked(self.idx)),
+            Type::String => Unexpected::Other("string"),
+            Type::Vector => Unexpected::Other("vector"),
+            Type::Table => Unexpected::Other("table"),
+            Type::Function => Unexpected::Other("function"),
+            Type::Userdata | Type::LightUserdata => Unexpected::Other("userdata"),
+            Type::Thread => Unexpected::Other("thread"),
+            Type::Buffer => Unexpected::Other("buffer"),
+        }
+    }
+
+    fn invalid_type<T>(&self, exp: &dyn de::Expected) -> Result<T, Error> {
+        Err(de::Error::invalid_type(self.unexpected(), exp))
+    }
+
+    fn is_array(&self) -> bool {
+        let len = self.stack.len(self.idx);
+        if len == 0 {
+            return false;
+        }
+
+        let mut count = 0;
+        let top = self.stack.get_top();
+
+        self.stack.reserve(2);
+        let array = self
+            .stack
+            .iter(self.idx, || {
+                count += 1;
+
+                match self.stack.to_number(-2) {
+                    Some(key) if key.fract() == 0.0 && key >= 1.0 && key <= len as f64 => {
+                        ControlFlow::Continue(())
+                    }
+                    _ => ControlFlow::Break(()),
+                }
+            })
+            .is_none()
+            && count == len;
+
+        self.stack.set_top(top);
+        array
+    }
+
+    fn visit_integer<'de, V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.stack.to_number(self.idx) {
+            Some(n) if n.fract() == 0.0 && n < 0.0 && n >= i64::MIN as f64 => {
+                visitor.visit_i64(n as i64)
+            }
+            Some(n) if n.fract() == 0.0 && n >= 0.0 && n < u64::MAX as f64 => {
+                visitor.visit_u64(n as u64)
+            }
+            _ => self.invalid_type(&visitor),
+        }
+    }
+
+    fn visit_vector<'de, V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let (x, y, z) = unsafe { self.stack.to_vector_unchecked(self.idx) };
+
+        if self.options.vectors_as_arrays {
+            visitor.visit_seq(de::value::SeqDeserializer::new([x, y, z].into_iter()))
+        } else {
+            let components = [("x", x), ("y", y), ("z", z)];
+            visitor.visit_map(de::value::MapDeserializer::new(components.into_iter()))
+        }
+    }
+
+    // Visitors can stop before the last element, so whatever the access left
+    // on the stack is dropped afterwards.
+    fn visit_array<'de, V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let stack = self.stack;
+        let top = stack.get_top();
+        let len = stack.len(self.idx);
+
+        let value = visitor.visit_seq(ArrayAccess {
+            de: self,
+            index: 0,
+            len,
+        });
+
+        stack.set_top(top);
+        value
+    }
+
+    fn visit_table<'de, V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let stack = self.stack;
+        let top = stack.get_top();
+
+        let keys = if self.options.sort_keys {
+            Some(self.sorted_keys()?.into_iter())
+        } else {
+            None
+        };
+
+        let value = visitor.visit_map(TableAccess {
+            de: self,
+            keys,
+            iter: 0,
+        });
+
+        stack.set_top(top);
+        value
+    }
+
+    fn sorted_keys(&self) -> Result<Vec<Key>, Error> {
+        let mut keys = Vec::new();
+        let top = self.stack.get_top();
+
+        self.stack.reserve(2);
+        let error = self.stack.iter(self.idx, || {
+            let key = match self.stack.type_of(-2) {
+                Type::Boolean => Key::Boolean(self.stack.to_boolean_unchecked(-2)),
+                Type::Number => Key::Number(self.stack.to_number_unchecked(-2)),
+                Type::String => {
+                    Key::String(unsafe { self.stack.to_string_slice_unchecked(-2) }.to_vec())
+                }
+                _ => return ControlFlow::Break(()),
+            };
+
+            keys.push(key);
+            ControlFlow::Continue(())
+        });
+
+        self.stack.set_top(top);
+
+        if error.is_some() {
+            return Err(Error(
+                "cannot sort table keys that aren't booleans, numbers or strings".into(),
+            ));
+        }
+
+        keys.sort_by(Key::cmp);
+        Ok(keys)
+    }
+}
+
+impl<'de, C: Config> de::Deserializer<'de> for Deserializer<'_, C> {
+    type Error = Error;
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let stack = self.stack;
+
+        match stack.type_of(self.idx) {
+            Type::None | Type::Nil => visitor.visit_unit(),
+            Type::Boolean => visitor.visit_bool(stack.to_boolean_unchecked(self.idx)),
+            Type::Number => {
+                let n = stack.to_number_unchecked(self.idx);
+
+                if n.fract() == 0.0 && n >= i64::MIN as f64 && n < u64::MAX as f64 {
+                    self.visit_integer(visitor)
+                } else {
+                    visitor.visit_f64(n)
+                }
+            }
+            Type::Vector => self.visit_vector(visitor),
+            Type::String => {
+                let bytes = unsafe { stack.to_string_slice_unchecked(self.idx) };
+
+                match std::str::from_utf8(bytes) {
+                    Ok(s) => visitor.visit_str(s),
+                    Err(_) => visitor.visit_bytes(bytes),
+                }
+            }
+            Type::Buffer => {
+                let (ptr, len) = unsafe { stack.to_buffer_unchecked(self.idx) };
+                visitor.visit_bytes(unsafe { std::slice::from_raw_parts(ptr, len) })
+            }
+            Type::Table if self.options.detect_arrays && self.is_array() => {
+                self.visit_array(visitor)
+            }
+            Type::Table => self.visit_table(visitor),
+            _ => self.invalid_type(&visitor),
+        }
+    }
+
+    fn deserialize_i8<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.visit_integer(visitor)
+    }
+
+    fn deserialize_i16<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.visit_integer(visitor)
+    }
+
+    fn deserialize_i32<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.visit_integer(visitor)
+    }
+
+    fn deserialize_i64<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.visit_integer(visitor)
+    }
+
+    fn deserialize_u8<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.visit_integer(visitor)
+    }
+
+    fn deserialize_u16<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.visit_integer(visitor)
+    }
+
+    fn deserialize_u32<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.visit_integer(visitor)
+    }
+
+    fn deserialize_u64<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.visit_integer(visitor)
+    }
+
+    fn deserialize_f32<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_f64<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.stack.to_number(self.idx) {
+            Some(n) => visitor.visit_f64(n),
+            None => self.invalid_type(&visitor),
+        }
+    }
+
+    fn deserialize_bytes<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let stack = self.stack;
+
+        match stack.type_of(self.idx) {
+            Type::String => {
+                visitor.visit_bytes(unsafe { stack.to_string_slice_unchecked(self.idx) })
+            }
+            Type::Buffer => self.deserialize_any(visitor),
+            Type::Table => self.visit_array(visitor),
+            _ => self.invalid_type(&visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.stack.type_of(self.idx) {
+            Type::None | Type::Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.stack.type_of(self.idx) {
+            Type::None | Type::Nil => visitor.visit_unit(),
+            _ => self.invalid_type(&visitor),
+        }
+    }
+
+    fn deserialize_unit_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.stack.type_of(self.idx) {
+            Type::Table => self.visit_array(visitor),
+            Type::Vector => self.visit_vector(visitor),
+            _ => self.invalid_type(&visitor),
+        }
+    }
+
+    fn deserialize_tuple<V: de::Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.stack.type_of(self.idx) {
+            Type::Table => self.visit_table(visitor),
+            Type::Vector => self.visit_vector(visitor),
+            _ => self.invalid_type(&visitor),
+        }
+    }
+
+    fn deserialize_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let stack = self.stack;
+
+        match stack.type_of(self.idx) {
+            Type::String => match stack.to_string_str(self.idx) {
+                Some(variant) => visitor.visit_enum(variant.into_deserializer()),
+                None => self.invalid_type(&visitor),
+            },
+            Type::Table => {
+                stack.reserve(4);
+
+                let iter = unsafe { sys::lua_rawiter(stack.as_ptr(), self.idx, 0) };
+                if iter < 0 || unsafe { sys::lua_rawiter(stack.as_ptr(), self.idx, iter) } >= 0 {
+                    return Err(Error("expected a table with a single variant key".into()));
+                }
+
+                let top = stack.get_top();
+                let value = visitor.visit_enum(VariantAccess {
+                    key: self.at(top - 1),
+                    value: self.at(top),
+                });
+
+                stack.set_top(top - 2);
+                value
+            }
+            _ => self.invalid_type(&visitor),
+        }
+    }
+
+    fn deserialize_ignored_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    ::serde::forward_to_deserialize_any! {
+        bool char str string identifier
+    }
+}
+
+struct ArrayAccess<'a, C: Config> {
+    de: Deserializer<'a, C>,
+    index: u32,
+    len: u32,
+}
+
+impl<'de, C: Config> de::SeqAccess<'de> for ArrayAccess<'_, C> {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        if self.index >= self.len {
+            return Ok(None);
+        }
+
+        self.index += 1;
+
+        let stack = self.de.stack;
+        stack.reserve(1);
+        stack.table_get_raw_i(self.de.idx, self.index);
+
+        let value = seed.deserialize(self.de.at(-1))?;
+        stack.pop(1);
+
+        Ok(Some(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some((self.len - self.index) as _)
+    }
+}
+
+// Table keys are either visited in iteration order, leaving the current key
+// and value on the stack, or in sorted order, pushing each key as it's read.
+struct TableAccess<'a, C: Config> {
+    de: Deserializer<'a, C>,
+    keys: Option<vec::IntoIter<Key>>,
+    iter: i32,
+}
+
+impl<'de, C: Config> de::MapAccess<'de> for TableAccess<'_, C> {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let stack = self.de.stack;
+        stack.reserve(2);
+
+        if let Some(keys) = &mut self.keys {
+            let Some(key) = keys.next() else {
+                return Ok(None);
+            };
+
+            key.push(stack);
+        } else {
+            if self.iter != 0 {
+                stack.pop(2);
+            }
+
+            self.iter = unsafe { sys::lua_rawiter(stack.as_ptr(), self.de.idx, self.iter) };
+            if self.iter < 0 {
+                return Ok(None);
+            }
+        }
+
+        let idx = if self.keys.is_some() { -1 } else { -2 };
+        seed.deserialize(self.de.at(idx)).map(Some)
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let stack = self.de.stack;
+
+        if self.keys.is_some() {
+            stack.reserve(1);
+            stack.table_get_raw(self.de.idx);
+
+            let value = seed.deserialize(self.de.at(-1))?;
+            stack.pop(1);
+
+            Ok(value)
+        } else {
+            seed.deserialize(self.de.at(-1))
+        }
+    }
+}
+
+struct VariantAccess<'a, C: Config> {
+    key: Deserializer<'a, C>,
+    value: Deserializer<'a, C>,
+}
+
+impl<'de, 'a, C: Config> de::EnumAccess<'de> for VariantAccess<'a, C> {
+    type Error = Error;
+    type Variant = Deserializer<'a, C>;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Deserializer<'a, C>), Error> {
+        Ok((seed.deserialize(self.key)?, self.value))
+    }
+}
+
+impl<'de, C: Config> de::VariantAccess<'de> for Deserializer<'_, C> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: de::Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: de::Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+enum Key {
+    Boolean(bool),
+    Number(f64),
+    String(Vec<u8>),
+}
+
+impl Key {
+    fn rank(&self) -> u8 {
+        match self {
+            Key::Boolean(_) => 0,
+            Key::Number(_) => 1,
+            Key::String(_) => 2,
+        }
+    }
+
+    fn cmp(a: &Key, b: &Key) -> Ordering {
+        match (a, b) {
+            (Key::Boolean(a), Key::Boolean(b)) => a.cmp(b),
+            (Key::Number(a), Key::Number(b)) => a.total_cmp(b),
+            (Key::String(a), Key::String(b)) => a.cmp(b),
+            _ => a.rank().cmp(&b.rank()),
+        }
+    }
+
+    fn push<C: Config>(&self, stack: &Stack<C>) {
+        match self {
+            Key::Boolean(value) => stack.push_boolean(*value),
+            Key::Number(value) => stack.push_number(*value),
+            Key::String(value) => stack.push_string(value),
+        }
+    }
+}
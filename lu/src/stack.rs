@@ -1,16 +1,20 @@
 use std::{
-    cell::RefCell,
-    ffi::{CStr, CString},
+    any::TypeId,
+    cell::{self, BorrowError, BorrowMutError, RefCell, RefMut},
+    ffi::{CStr, CString, c_void},
     marker::PhantomData,
     ops::ControlFlow,
     ptr::NonNull,
 };
 
 use crate::{
-    Bytecode, Config, Context, FnReturn, Function, Ref, Status, Thread, ThreadMain, ThreadRef,
-    Type, Userdata,
+    Bytecode, Config, Context, Environment, FnReturn, Function, LightUserdata, Ref, Status, Thread,
+    ThreadMain, ThreadRef, Type, UnregisteredUserdata, Userdata, UserdataKind, state::Shared,
+    userdata::Cast,
 };
 
+const USERVALUES_KEY: &CStr = c"lu.uservalues";
+
 #[repr(transparent)]
 pub struct Stack<C: Config>(
     pub(crate) NonNull<sys::lua_State>,
@@ -99,6 +103,10 @@ impl<C: Config> Stack<C> {
         unsafe { sys::lua_pushlightuserdata(self.as_ptr(), value.cast()) }
     }
 
+    pub fn push_light<T: LightUserdata>(&self, value: *mut T) {
+        unsafe { sys::lua_pushlightuserdatatagged(self.as_ptr(), value.cast(), T::tag() as _) }
+    }
+
     pub fn push_number(&self, value: f64) {
         unsafe { sys::lua_pushnumber(self.as_ptr(), value) }
     }
@@ -186,28 +194,69 @@ impl<C: Config> Stack<C> {
         }
     }
 
-    pub fn push_bytecode(&self, name: &CStr, bytecode: Bytecode) {
-        unsafe {
+    // Pushes the loaded chunk, or returns the error without pushing
+    // anything.
+    pub fn push_bytecode(&self, name: &CStr, bytecode: Bytecode) -> Result<(), String> {
+        self.load(name, bytecode, 0)
+    }
+
+    // Loads the bytecode with `env` as its globals instead of the thread's.
+    pub fn load_with_env(
+        &self,
+        name: &CStr,
+        bytecode: Bytecode,
+        env: &Environment<C>,
+    ) -> Result<(), String> {
+        env.push(self);
+
+        let result = self.load(name, bytecode, -1);
+
+        match result {
+            Ok(()) => self.remove(-2),
+            Err(_) => self.pop(1),
+        }
+
+        result
+    }
+
+    fn load(&self, name: &CStr, bytecode: Bytecode, env: i32) -> Result<(), String> {
+        let status = unsafe {
             sys::luau_load(
                 self.as_ptr(),
                 name.as_ptr(),
                 bytecode.ptr(),
                 bytecode.len(),
-                0,
+                env,
             )
         };
+
+        if status == 0 {
+            return Ok(());
+        }
+
+        let err = match self.to_string_slice(-1) {
+            Some(err) => String::from_utf8_lossy(err).into_owned(),
+            None => "failed to load bytecode".into(),
+        };
+
+        self.pop(1);
+        Err(err)
     }
 
     pub fn push_userdata<T: Userdata>(&self, value: T) {
         let tag = T::tag();
         let size = size_of::<RefCell<T>>();
 
-        unsafe {
-            #[cfg(debug_assertions)]
-            if sys::lua_getuserdatadtor(self.as_ptr(), tag as _).is_none() {
-                panic!("attempt to push unregistered userdata type: {}", T::name());
-            }
+        if UserdataKind::of::<T>() == UserdataKind::Untagged {
+            return self.push_userdata_untagged(value);
+        }
 
+        #[cfg(debug_assertions)]
+        if !self.is_registered::<T>() {
+            panic!("attempt to push unregistered userdata type: {}", T::name());
+        }
+
+        unsafe {
             let ptr = sys::lua_newuserdatataggedwithmetatable(self.as_ptr(), size, tag as _);
             let ptr = ptr.cast::<RefCell<T>>();
 
@@ -215,6 +264,113 @@ impl<C: Config> Stack<C> {
         }
     }
 
+    fn push_userdata_untagged<T: Userdata>(&self, value: T) {
+        extern "C-unwind" fn dtor<T>(ud: *mut c_void) {
+            unsafe { ud.cast::<RefCell<T>>().drop_in_place() };
+        }
+
+        let shared = unsafe { Shared::get(self.as_ptr()) };
+        let metatable = shared
+            .userdata
+            .borrow()
+            .get(&T::tag())
+            .and_then(|registered| registered.untagged.as_ref())
+            .map(|untagged| untagged.metatable);
+
+        let Some(metatable) = metatable else {
+            panic!("attempt to push unregistered userdata type: {}", T::name());
+        };
+
+        unsafe {
+            let size = size_of::<RefCell<T>>();
+            let ptr = sys::lua_newuserdatadtor(self.as_ptr(), size, Some(dtor::<T>));
+            ptr.cast::<RefCell<T>>().write(RefCell::new(value));
+
+            self.reserve(1);
+            sys::lua_getref(self.as_ptr(), metatable);
+            sys::lua_setmetatable(self.as_ptr(), -2);
+        }
+    }
+
+    // Pushes the uservalue table of the userdata, creating it if it doesn't
+    // exist yet, or nil if the value isn't a userdata.
+    pub fn push_userdata_uservalue(&self, idx: i32) {
+        let idx = self.abs_idx(idx);
+        self.reserve(4);
+
+        if self.type_of(idx) != Type::Userdata {
+            self.push_nil();
+            return;
+        }
+
+        self.push_uservalues();
+        self.push_copy(idx);
+        self.table_get_raw(-2);
+
+        if self.is_nil(-1) {
+            self.pop(1);
+            self.push_table();
+
+            self.push_copy(idx);
+            self.push_copy(-2);
+            self.table_set_raw(-4);
+        }
+
+        self.remove(-2);
+    }
+
+    // Pushes `uservalue[key]` for the userdata at `idx`, without creating the
+    // uservalue table.
+    pub(crate) fn uservalue_get(&self, idx: i32, key: i32) {
+        let (idx, key) = (self.abs_idx(idx), self.abs_idx(key));
+        self.reserve(3);
+
+        self.push_uservalues();
+        self.push_copy(idx);
+        self.table_get_raw(-2);
+
+        if self.is_table(-1) {
+            self.push_copy(key);
+            self.table_get_raw(-2);
+            self.remove(-2);
+        }
+
+        self.remove(-2);
+    }
+
+    // Uservalues live in a registry table with weak keys, so they're
+    // collected along with their userdata.
+    fn push_uservalues(&self) {
+        self.table_get_raw_field(sys::LUA_REGISTRYINDEX, USERVALUES_KEY);
+
+        if self.is_nil(-1) {
+            self.pop(1);
+            self.push_table();
+
+            self.push_table();
+            self.push_string("k");
+            self.table_set_raw_field(-2, c"__mode");
+            unsafe { sys::lua_setmetatable(self.as_ptr(), -2) };
+
+            self.push_copy(-1);
+            self.table_set_raw_field(sys::LUA_REGISTRYINDEX, USERVALUES_KEY);
+        }
+    }
+
+    pub fn try_push_userdata<T: Userdata>(&self, value: T) -> Result<(), UnregisteredUserdata<T>> {
+        if self.is_registered::<T>() {
+            self.push_userdata(value);
+            Ok(())
+        } else {
+            Err(UnregisteredUserdata(value))
+        }
+    }
+
+    pub(crate) fn is_registered<T: Userdata>(&self) -> bool {
+        let shared = unsafe { Shared::get(self.as_ptr()) };
+        shared.userdata.borrow().contains_key(&T::tag())
+    }
+
     pub fn push_thread(&self, thread: &Thread<C>) {
         if thread.as_ptr() == self.as_ptr() {
             unsafe { sys::lua_pushthread(self.as_ptr()) };
@@ -289,7 +445,37 @@ impl<C: Config> Stack<C> {
     }
 
     pub fn is_userdata<T: Userdata>(&self, idx: i32) -> bool {
-        unsafe { sys::lua_userdatatag(self.as_ptr(), idx) == (T::tag() as _) }
+        match UserdataKind::of::<T>() {
+            UserdataKind::Tagged => unsafe {
+                sys::lua_userdatatag(self.as_ptr(), idx) == (T::tag() as _)
+            },
+            UserdataKind::Untagged => self.userdata_tag(idx) == Some(T::tag()),
+        }
+    }
+
+    // Finds the tag of a userdata pushed by `push_userdata`, looking at the
+    // metatable when the userdata isn't tagged.
+    pub(crate) fn userdata_tag(&self, idx: i32) -> Option<u32> {
+        let tag = unsafe { sys::lua_userdatatag(self.as_ptr(), idx) };
+
+        if tag > 0 && tag < sys::LUA_UTAG_LIMIT {
+            return Some(tag as _);
+        }
+
+        if self.type_of(idx) != Type::Userdata {
+            return None;
+        }
+
+        self.reserve(1);
+        if unsafe { sys::lua_getmetatable(self.as_ptr(), idx) } == 0 {
+            return None;
+        }
+
+        let ptr = unsafe { sys::lua_topointer(self.as_ptr(), -1) };
+        self.pop(1);
+
+        let shared = unsafe { Shared::get(self.as_ptr()) };
+        shared.untagged.borrow().get(&ptr).copied()
     }
 
     pub fn is_thread(&self, idx: i32) -> bool {
@@ -324,6 +510,19 @@ impl<C: Config> Stack<C> {
         }
     }
 
+    pub fn is_light<T: LightUserdata>(&self, idx: i32) -> bool {
+        self.is_light_userdata(idx)
+            && unsafe { sys::lua_lightuserdatatag(self.as_ptr(), idx) } == T::tag() as _
+    }
+
+    pub fn to_light<T: LightUserdata>(&self, idx: i32) -> Option<*mut T> {
+        if self.is_light::<T>(idx) {
+            Some(self.to_light_userdata_unchecked(idx))
+        } else {
+            None
+        }
+    }
+
     pub fn to_number_unchecked(&self, idx: i32) -> f64 {
         unsafe { sys::lua_tonumber(self.as_ptr(), idx as _) }
     }
@@ -375,11 +574,67 @@ impl<C: Config> Stack<C> {
     }
 
     pub fn to_userdata<T: Userdata>(&self, idx: i32) -> Option<&RefCell<T>> {
-        unsafe {
-            sys::lua_touserdatatagged(self.as_ptr(), idx, T::tag() as _)
-                .cast::<RefCell<T>>()
-                .as_ref()
-        }
+        let ptr = match UserdataKind::of::<T>() {
+            UserdataKind::Tagged => unsafe {
+                sys::lua_touserdatatagged(self.as_ptr(), idx, T::tag() as _)
+            },
+            UserdataKind::Untagged if self.is_userdata::<T>(idx) => unsafe {
+                sys::lua_touserdata(self.as_ptr(), idx)
+            },
+            UserdataKind::Untagged => return None,
+        };
+
+        unsafe { ptr.cast::<RefCell<T>>().as_ref() }
+    }
+
+    // Names the registered userdata type of the value, for diagnostics.
+    pub fn to_userdata_any(&self, idx: i32) -> Option<&'static str> {
+        let tag = self.userdata_tag(idx)?;
+
+        let shared = unsafe { Shared::get(self.as_ptr()) };
+        let userdata = shared.userdata.borrow();
+
+        userdata.get(&tag).map(|registered| registered.info.name)
+    }
+
+    pub fn is_userdata_dyn<T: ?Sized + 'static>(&self, idx: i32) -> bool {
+        self.with_cast::<T, _>(idx, |_, _| ()).is_some()
+    }
+
+    // Looks up the cast registered by `Methods::extends` or
+    // `Methods::implements` from the type of the userdata to `T`.
+    fn with_cast<'a, T: ?Sized + 'static, R>(
+        &'a self,
+        idx: i32,
+        func: impl FnOnce(&Cast<T>, &'a c_void) -> R,
+    ) -> Option<R> {
+        let tag = self.userdata_tag(idx)?;
+
+        let shared = unsafe { Shared::get(self.as_ptr()) };
+        let userdata = shared.userdata.borrow();
+
+        let cast = userdata
+            .get(&tag)?
+            .casts
+            .get(&TypeId::of::<T>())?
+            .downcast_ref::<Cast<T>>()?;
+
+        let ud = unsafe { &*sys::lua_touserdata(self.as_ptr(), idx).cast::<c_void>() };
+        Some(func(cast, ud))
+    }
+
+    pub(crate) fn borrow_cast<T: ?Sized + 'static>(
+        &self,
+        idx: i32,
+    ) -> Option<Result<cell::Ref<'_, T>, BorrowError>> {
+        self.with_cast(idx, |cast, ud| (cast.borrow)(ud))
+    }
+
+    pub(crate) fn borrow_mut_cast<T: ?Sized + 'static>(
+        &self,
+        idx: i32,
+    ) -> Option<Result<RefMut<'_, T>, BorrowMutError>> {
+        self.with_cast(idx, |cast, ud| (cast.borrow_mut)(ud))
     }
 
     pub unsafe fn to_thread_unchecked(&self, idx: i32) -> ThreadRef<C> {
@@ -430,9 +685,14 @@ impl<C: Config> Stack<C> {
     }
 
     pub fn to_ref(&self, idx: i32) -> Ref<C> {
-        Ref(self.main(), unsafe {
-            sys::lua_ref(self.as_ptr(), idx) as _
-        })
+        let shared = unsafe { Shared::get(self.as_ptr()) };
+        let id = unsafe { sys::lua_ref(self.as_ptr(), idx) as u32 };
+
+        let serial = shared.ref_serial.get();
+        shared.ref_serial.set(serial + 1);
+        shared.refs.borrow_mut().insert(serial);
+
+        Ref(self.main(), id, serial)
     }
 
     pub fn table_get(&self, tblidx: i32) {
@@ -483,6 +743,108 @@ impl<C: Config> Stack<C> {
         unsafe { sys::lua_getreadonly(self.as_ptr(), tblidx) != 0 }
     }
 
+    pub fn set_safeenv(&self, tblidx: i32, enabled: bool) {
+        unsafe { sys::lua_setsafeenv(self.as_ptr(), tblidx, enabled as _) };
+    }
+
+    // Pushes a copy of the Luau function at `idx` that shares its upvalues
+    // and uses the current thread's globals. Returns `false` without pushing
+    // anything for other values, including C functions.
+    pub fn clone_function(&self, idx: i32) -> bool {
+        if unsafe { sys::lua_isLfunction(self.as_ptr(), idx) } == 0 {
+            return false;
+        }
+
+        self.reserve(1);
+        unsafe { sys::lua_clonefunction(self.as_ptr(), idx) };
+        true
+    }
+
+    // Like `clone_function`, but the copy uses `env` as its globals, so that a
+    // chunk loaded once can be run in many environments.
+    pub fn clone_function_with_env(&self, idx: i32, env: &Environment<C>) -> bool {
+        if !self.clone_function(idx) {
+            return false;
+        }
+
+        env.push(self);
+        unsafe { sys::lua_setfenv(self.as_ptr(), -2) };
+        true
+    }
+
+    // Pushes a shallow copy of the table at `idx`, returning `false` without
+    // pushing anything for other values.
+    pub fn clone_table(&self, idx: i32) -> bool {
+        if !self.is_table(idx) {
+            return false;
+        }
+
+        self.reserve(1);
+        unsafe { sys::lua_clonetable(self.as_ptr(), idx) };
+        true
+    }
+
+    // Pushes a copy of the table at `idx` where nested tables are copied too.
+    // Tables reached more than once, including through cycles, are copied
+    // once. Keys and metatables are shared with the original.
+    pub fn clone_table_deep(&self, idx: i32) -> bool {
+        if !self.is_table(idx) {
+            return false;
+        }
+
+        let idx = self.abs_idx(idx);
+
+        self.reserve(1);
+        self.push_table();
+        let seen = self.get_top();
+
+        self.clone_table_seen(idx, seen);
+        self.remove(seen);
+        true
+    }
+
+    fn clone_table_seen(&self, idx: i32, seen: i32) {
+        self.reserve(5);
+
+        self.push_copy(idx);
+        self.table_get_raw(seen);
+
+        if !self.is_nil(-1) {
+            return;
+        }
+
+        self.pop(1);
+        unsafe { sys::lua_clonetable(self.as_ptr(), idx) };
+        let clone = self.get_top();
+
+        self.push_copy(idx);
+        self.push_copy(clone);
+        self.table_set_raw(seen);
+
+        self.iter(clone, || {
+            if self.is_table(-1) {
+                self.clone_table_seen(self.get_top(), seen);
+                self.push_copy(-3);
+                self.push_copy(-2);
+                self.table_set_raw(clone);
+                self.pop(1);
+            }
+
+            ControlFlow::<()>::Continue(())
+        });
+    }
+
+    // Removes every entry of the table at `idx`, returning `false` if it
+    // isn't a table or is read-only.
+    pub fn clear_table(&self, idx: i32) -> bool {
+        if !self.is_table(idx) || self.table_get_readonly(idx) {
+            return false;
+        }
+
+        unsafe { sys::lua_cleartable(self.as_ptr(), idx) };
+        true
+    }
+
     pub fn len(&self, idx: i32) -> u32 {
         unsafe { sys::lua_objlen(self.as_ptr(), idx as _) as u32 }
     }
@@ -1,14 +1,198 @@
-use std::{cell::RefCell, ffi, marker::PhantomData, ptr::NonNull};
+use std::{
+    any::{Any, TypeId},
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    ffi, fmt,
+    marker::PhantomData,
+    mem,
+    ops::ControlFlow,
+    ptr::NonNull,
+    rc::Rc,
+};
 
 use crate::{
-    Config, Library, LuauAllocator, Methods, Stack, Thread, ThreadData, ThreadMain, ThreadRef,
-    Userdata,
+    Config, Context, FnReturn, Function, Library, LightUserdata, LuauAllocator, Methods,
+    RegisterError, Stack, Thread, ThreadData, ThreadMain, ThreadRef, Type, Userdata, UserdataInfo,
+    UserdataKind, atom, userdata::Upcast,
 };
 
+pub(crate) struct Shared {
+    // Serial numbers of live `Ref`s, which count up so that `State::rollback`
+    // can tell which are newer. They're tracked by serial rather than by
+    // registry slot, since every ref to nil shares the slot `LUA_REFNIL`.
+    pub(crate) refs: RefCell<HashSet<u64>>,
+    pub(crate) ref_serial: Cell<u64>,
+    pub(crate) userdata: RefCell<HashMap<u32, Registered>>,
+    // Tags of untagged userdata types by their metatable pointer.
+    pub(crate) untagged: RefCell<HashMap<*const ffi::c_void, u32>>,
+    // Whether the globals have safeenv set, which Luau has no getter for.
+    pub(crate) safeenv: Cell<bool>,
+}
+
+pub(crate) struct Registered {
+    pub(crate) info: UserdataInfo,
+    pub(crate) methods: i32,
+    pub(crate) casts: HashMap<TypeId, Box<dyn Any>>,
+    pub(crate) dispatch: Option<Box<Dispatch>>,
+    // The type's `Rc<Fields<C>>`, which its children build on. It's held as
+    // a raw pointer since `Registered` isn't generic over the config.
+    pub(crate) fields: NonNull<()>,
+    pub(crate) drop_fields: unsafe fn(NonNull<()>),
+    pub(crate) untagged: Option<Untagged>,
+}
+
+impl Drop for Registered {
+    fn drop(&mut self) {
+        unsafe { (self.drop_fields)(self.fields) }
+    }
+}
+
+// Methods indexed by the atom of their name, used by `__namecall`.
+pub(crate) type Dispatch = Vec<Option<sys::lua_CFunction>>;
+
+// Resolves the fields of a userdata type and those it inherits. Values are
+// taken as `dyn Any` so that a child can pass itself through an `Upcast` to
+// its parent's fields.
+pub(crate) struct Fields<C: Config> {
+    get: fn(&dyn Any, &Stack<C>, &str) -> bool,
+    set: fn(&mut dyn Any, &Context<C>, &str, u32) -> bool,
+    parent: Option<(Upcast, Rc<Fields<C>>)>,
+    empty: bool,
+}
+
+impl<C: Config> Fields<C> {
+    fn new<U: Userdata>(parent: Option<(Upcast, Rc<Fields<C>>)>) -> Self {
+        fn get<C: Config, U: Userdata>(ud: &dyn Any, stack: &Stack<C>, field: &str) -> bool {
+            ud.downcast_ref::<U>()
+                .is_some_and(|ud| ud.get_field(stack, field))
+        }
+
+        fn set<C: Config, U: Userdata>(
+            ud: &mut dyn Any,
+            ctx: &Context<C>,
+            field: &str,
+            narg: u32,
+        ) -> bool {
+            ud.downcast_mut::<U>()
+                .is_some_and(|ud| ud.set_field(ctx, field, narg))
+        }
+
+        let empty = U::fields().is_empty() && parent.as_ref().is_none_or(|(_, p)| p.empty);
+
+        Self {
+            get: get::<C, U>,
+            set: set::<C, U>,
+            parent,
+            empty,
+        }
+    }
+
+    // The `Registered` must be for a type opened in a `State` with config `C`.
+    unsafe fn of(registered: &Registered) -> Rc<Self> {
+        let ptr = registered.fields.as_ptr().cast::<Self>();
+
+        unsafe {
+            Rc::increment_strong_count(ptr);
+            Rc::from_raw(ptr)
+        }
+    }
+
+    unsafe fn drop_raw(ptr: NonNull<()>) {
+        drop(unsafe { Rc::from_raw(ptr.as_ptr().cast::<Self>()) });
+    }
+
+    fn get(&self, ud: &dyn Any, stack: &Stack<C>, field: &str) -> bool {
+        (self.get)(ud, stack, field)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|(upcast, parent)| parent.get((upcast.as_ref)(ud), stack, field))
+    }
+
+    fn set(&self, ud: &mut dyn Any, ctx: &Context<C>, field: &str, narg: u32) -> bool {
+        (self.set)(ud, ctx, field, narg)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|(upcast, parent)| parent.set((upcast.as_mut)(ud), ctx, field, narg))
+    }
+}
+
+// Userdata types past `LUA_UTAG_LIMIT` are recognized by their metatable,
+// which is kept alive by a registry reference.
+pub(crate) struct Untagged {
+    pub(crate) metatable: i32,
+}
+
+impl Shared {
+    pub(crate) unsafe fn get<'a>(ptr: *mut sys::lua_State) -> &'a Shared {
+        unsafe {
+            (*sys::lua_callbacks(ptr))
+                .userdata
+                .cast::<Shared>()
+                .as_ref()
+                .unwrap_unchecked()
+        }
+    }
+}
+
+struct OpenLibrary<C: Config> {
+    name: &'static str,
+    library: Library<C>,
+    loaded: bool,
+}
+
+// Kept behind a pointer so that the globals `__index` hook can reach it.
+type Libraries<C> = RefCell<Vec<OpenLibrary<C>>>;
+
+// The globals and string keys of the registry as of `State::checkpoint`, each
+// held by a registry reference.
+struct Checkpoint {
+    globals: i32,
+    registry: i32,
+    readonly: bool,
+    safeenv: bool,
+    ref_serial: u64,
+    libraries: Vec<(&'static str, bool)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckpointError {
+    // A table reachable from the globals can be written to, so changes made
+    // to it couldn't be rolled back. Holds the path to the table.
+    MutableTable(String),
+    NoCheckpoint,
+    // `Ref`s created since the checkpoint are still alive, and would keep
+    // values from the rolled back state.
+    LiveRefs(usize),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::MutableTable(path) => {
+                write!(
+                    f,
+                    "table {path} is reachable from the globals but not read-only"
+                )
+            }
+            CheckpointError::NoCheckpoint => write!(f, "there is no checkpoint"),
+            CheckpointError::LiveRefs(n) => {
+                write!(f, "{n} refs created since the checkpoint are still alive")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
 pub struct State<C: Config> {
-    libraries: Vec<(&'static str, Library<C>)>,
+    libraries: NonNull<Libraries<C>>,
+    lazy: bool,
+    checkpoint: Option<Checkpoint>,
     alloc: NonNull<C::Allocator>,
     main: NonNull<RefCell<C::MainData>>,
+    shared: NonNull<Shared>,
     ptr: NonNull<sys::lua_State>,
 }
 
@@ -16,6 +200,8 @@ impl<C: Config> Drop for State<C> {
     fn drop(&mut self) {
         unsafe {
             sys::lua_close(self.ptr.as_ptr());
+            drop(Box::from_raw(self.shared.as_ptr()));
+            drop(Box::from_raw(self.libraries.as_ptr()));
             drop(Box::from_raw(self.main.as_ptr()));
             drop(Box::from_raw(self.alloc.as_ptr()));
         }
@@ -26,6 +212,15 @@ impl<C: Config> State<C> {
     pub fn new(main_data: C::MainData, alloc: C::Allocator) -> Self {
         let main = NonNull::new(Box::into_raw(Box::new(RefCell::new(main_data)))).unwrap();
         let alloc = NonNull::new(Box::into_raw(Box::new(alloc))).unwrap();
+        let shared = Shared {
+            refs: RefCell::default(),
+            ref_serial: Cell::new(0),
+            userdata: RefCell::default(),
+            untagged: RefCell::default(),
+            safeenv: Cell::new(false),
+        };
+        let shared = NonNull::new(Box::into_raw(Box::new(shared))).unwrap();
+        let libraries = NonNull::new(Box::into_raw(Box::default())).unwrap();
 
         extern "C-unwind" fn alloc_fn<Alloc: LuauAllocator>(
             ud: *mut ffi::c_void,
@@ -76,13 +271,18 @@ impl<C: Config> State<C> {
 
         unsafe {
             let callbacks = sys::lua_callbacks(ptr);
+            (*callbacks).userdata = shared.as_ptr().cast();
             (*callbacks).userthread = Some(userthread::<C>);
+            (*callbacks).useratom = Some(atom::useratom);
         }
 
         Self {
-            libraries: Vec::new(),
+            libraries,
+            lazy: false,
+            checkpoint: None,
             alloc,
             main,
+            shared,
             ptr: NonNull::new(ptr).unwrap(),
         }
     }
@@ -103,27 +303,463 @@ impl<C: Config> State<C> {
         unsafe { self.main.as_ref() }
     }
 
+    /// Wraps the state so that it can be moved to another OS thread. Fails
+    /// while `Ref`s or `ThreadRef`s into it are alive.
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may still reach the VM from this thread: no `ThreadMain`
+    /// or `Thread` handles taken from it may outlive the call, and no
+    /// userdata pushed into it may hold data shared with this thread, such
+    /// as an `Rc` or a reference to a `RefCell` the host keeps using. Every
+    /// userdata type it holds must be `Send`.
+    pub unsafe fn into_send(self) -> Result<SendableState<C>, Self> {
+        if unsafe { self.shared.as_ref() }.refs.borrow().is_empty() {
+            Ok(SendableState(self))
+        } else {
+            Err(self)
+        }
+    }
+
     pub fn open_library(&mut self, name: &'static str, library: Library<C>) {
+        self.close_library(name);
+
         let stack = self.stack();
         stack.reserve(3);
 
         stack.push_string(name);
-        library.push(stack);
+        push_library(stack, sys::LUA_GLOBALSINDEX, &library);
+        set_global(stack, sys::LUA_GLOBALSINDEX);
+
+        self.insert_library(name, library, true);
+    }
+
+    // The library's table isn't built until a script first reads the global.
+    pub fn open_library_lazy(&mut self, name: &'static str, library: Library<C>) {
+        self.close_library(name);
+
+        if !self.lazy {
+            self.install_loader();
+            self.lazy = true;
+        }
+
+        self.insert_library(name, library, false);
+    }
+
+    fn insert_library(&mut self, name: &'static str, library: Library<C>, loaded: bool) {
+        let libraries = unsafe { self.libraries.as_ref() };
+
+        libraries.borrow_mut().push(OpenLibrary {
+            name,
+            library,
+            loaded,
+        });
+    }
+
+    // Installs the globals `__index` hook that loads lazy libraries. Keys
+    // that aren't lazy libraries fall through to whatever `__index` the
+    // globals already had, which is kept as the second upvalue. The globals
+    // themselves are the third, so that the hook refuses any other table when
+    // it's called directly.
+    fn install_loader(&self) {
+        extern "C-unwind" fn load<C: Config>(ctx: Context<C>) -> FnReturn {
+            if unsafe { sys::lua_rawequal(ctx.as_ptr(), 1, sys::lua_upvalueindex(3)) } == 0 {
+                ctx.error_msg("the library loader can only index the globals");
+            }
+
+            let libraries =
+                ctx.to_light_userdata_unchecked::<Libraries<C>>(sys::lua_upvalueindex(1));
+            let libraries = unsafe { &*libraries };
+
+            let name = match ctx.type_of(2) {
+                Type::String => ctx.to_string_str(2),
+                _ => None,
+            };
+
+            // The library is taken out while it's pushed, since pushing can run
+            // scripts that load other libraries.
+            let taken = name.and_then(|name| {
+                let mut libraries = libraries.borrow_mut();
+                let entry = libraries
+                    .iter_mut()
+                    .find(|entry| !entry.loaded && entry.name == name)?;
+
+                entry.loaded = true;
+                Some((entry.name, mem::take(&mut entry.library)))
+            });
 
-        stack.table_set_raw(sys::LUA_GLOBALSINDEX);
+            let Some((name, library)) = taken else {
+                return fallback(ctx);
+            };
 
-        self.libraries.push((name, library))
+            let mut guard = Taken {
+                libraries,
+                name,
+                library,
+                pushed: false,
+            };
+
+            push_library(&ctx, 1, &guard.library);
+            guard.pushed = true;
+            drop(guard);
+
+            // The first argument is the table whose `__index` fired, which is
+            // the real globals even when the script runs in a sandboxed thread
+            // or an `Environment` that reads through to them.
+            ctx.reserve(2);
+            ctx.push_copy(2);
+            ctx.push_copy(-2);
+            set_global(&ctx, 1);
+
+            ctx.ret_with(1)
+        }
+
+        // Puts a taken library back when it's dropped. If pushing it raised an
+        // error, it's left to be loaded again on the next read.
+        struct Taken<'a, C: Config> {
+            libraries: &'a Libraries<C>,
+            name: &'static str,
+            library: Library<C>,
+            pushed: bool,
+        }
+
+        impl<C: Config> Drop for Taken<'_, C> {
+            fn drop(&mut self) {
+                let Ok(mut libraries) = self.libraries.try_borrow_mut() else {
+                    return;
+                };
+
+                if let Some(entry) = libraries
+                    .iter_mut()
+                    .find(|entry| entry.loaded && entry.name == self.name)
+                {
+                    entry.library = mem::take(&mut self.library);
+                    entry.loaded = self.pushed;
+                }
+            }
+        }
+
+        fn fallback<C: Config>(ctx: Context<C>) -> FnReturn {
+            let index = sys::lua_upvalueindex(2);
+            ctx.reserve(3);
+
+            match ctx.type_of(index) {
+                Type::Function => {
+                    ctx.push_copy(index);
+                    ctx.push_copy(1);
+                    ctx.push_copy(2);
+                    unsafe { sys::lua_call(ctx.as_ptr(), 2, 1) };
+                }
+                Type::None | Type::Nil => ctx.push_nil(),
+                _ => {
+                    ctx.push_copy(2);
+                    ctx.table_get(index);
+                }
+            }
+
+            ctx.ret_with(1)
+        }
+
+        let stack = self.stack();
+        stack.reserve(4);
+
+        let existing = unsafe { sys::lua_getmetatable(self.as_ptr(), sys::LUA_GLOBALSINDEX) } != 0;
+
+        if !existing {
+            stack.push_table();
+        }
+
+        let frozen = stack.table_get_readonly(-1);
+
+        if frozen {
+            stack.table_set_readonly(-1, false);
+        }
+
+        stack.push_light_userdata(self.libraries.as_ptr());
+        stack.table_get_raw_field(-2, c"__index");
+        stack.push_copy(sys::LUA_GLOBALSINDEX);
+        stack.push_extern_closure(c"__index", 3, load::<C>);
+        stack.table_set_raw_field(-2, c"__index");
+
+        // Keeps scripts from reaching the hook through `getmetatable(_G)`.
+        stack.table_get_raw_field(-1, c"__metatable");
+        if stack.is_nil(-1) {
+            stack.push_string("The metatable is locked");
+            stack.table_set_raw_field(-3, c"__metatable");
+        }
+        stack.pop(1);
+
+        stack.table_set_readonly(-1, frozen || !existing);
+
+        if existing {
+            stack.pop(1);
+            return;
+        }
+
+        let sandboxed = stack.table_get_readonly(sys::LUA_GLOBALSINDEX);
+
+        if sandboxed {
+            stack.table_set_readonly(sys::LUA_GLOBALSINDEX, false);
+        }
+
+        unsafe { sys::lua_setmetatable(self.as_ptr(), sys::LUA_GLOBALSINDEX) };
+
+        if sandboxed {
+            stack.table_set_readonly(sys::LUA_GLOBALSINDEX, true);
+        }
+    }
+
+    // Includes lazy libraries once they've been loaded.
+    pub fn loaded_libraries(&self) -> Vec<&'static str> {
+        let libraries = unsafe { self.libraries.as_ref() };
+
+        libraries
+            .borrow()
+            .iter()
+            .filter(|entry| entry.loaded)
+            .map(|entry| entry.name)
+            .collect()
     }
 
-    pub fn open_userdata<U: Userdata>(&self, methods: Methods<C>) {
-        let methods = methods.methods;
+    // Removes the library's global, returning whether it was open.
+    pub fn close_library(&mut self, name: &str) -> bool {
+        let libraries = unsafe { self.libraries.as_ref() };
+
+        let mut libraries = libraries.borrow_mut();
+        let Some(i) = libraries.iter().position(|entry| entry.name == name) else {
+            return false;
+        };
+
+        let entry = libraries.remove(i);
+        drop(libraries);
+
+        if entry.loaded {
+            let stack = self.stack();
+            stack.reserve(2);
+
+            stack.push_string(name);
+            stack.push_nil();
+            set_global(stack, sys::LUA_GLOBALSINDEX);
+        }
+
+        true
+    }
+
+    pub fn open_userdata<U: Userdata>(&self, methods: Methods<C>) -> Result<(), RegisterError> {
+        let Methods {
+            methods,
+            parent,
+            upcast,
+            casts,
+            ..
+        } = methods;
+
+        if self.is_registered::<U>() {
+            return Err(RegisterError::AlreadyRegistered(U::name()));
+        }
+
+        if let Some(&(_, source)) = casts
+            .iter()
+            .map(|entry| &entry.source)
+            .find(|(tag, _)| *tag != U::tag())
+        {
+            return Err(RegisterError::MethodsForOtherType {
+                name: U::name(),
+                source,
+            });
+        }
+
+        let shared = unsafe { self.shared.as_ref() };
+
+        let parent = match parent {
+            Some((tag, name)) => {
+                let userdata = shared.userdata.borrow();
+                let Some(parent) = userdata.get(&tag) else {
+                    return Err(RegisterError::ParentNotRegistered {
+                        name: U::name(),
+                        parent: name,
+                    });
+                };
+
+                Some((
+                    parent.info.name,
+                    parent.methods,
+                    parent.dispatch.clone(),
+                    unsafe { Fields::<C>::of(parent) },
+                ))
+            }
+            None => None,
+        };
+
+        let fields = upcast.zip(parent.as_ref().map(|(_, _, _, fields)| fields.clone()));
+        let fields = Rc::new(Fields::<C>::new::<U>(fields));
+
+        // Continuations can't be called through `__namecall`, since it would
+        // be the function resumed after a yield, so those types and their
+        // children index the methods table instead.
+        let dispatch = match &parent {
+            Some((_, _, None, _)) => None,
+            _ if methods
+                .iter()
+                .any(|(_, func)| matches!(func, Function::Continuation { .. })) =>
+            {
+                None
+            }
+            _ => {
+                let mut dispatch = parent
+                    .as_ref()
+                    .and_then(|(_, _, dispatch, _)| dispatch.as_deref().cloned())
+                    .unwrap_or_default();
+
+                for (name, func) in &methods {
+                    let Function::Normal { func, .. } = func else {
+                        unreachable!()
+                    };
+
+                    let Some(atom) = atom::atom(name) else {
+                        continue;
+                    };
+
+                    let atom = atom as usize;
+                    if dispatch.len() <= atom {
+                        dispatch.resize(atom + 1, None);
+                    }
+
+                    dispatch[atom] = Some(unsafe {
+                        std::mem::transmute::<
+                            extern "C-unwind" fn(Context<C>) -> FnReturn,
+                            sys::lua_CFunction,
+                        >(*func)
+                    });
+                }
+
+                Some(Box::new(dispatch))
+            }
+        };
+
+        let casts = casts
+            .into_iter()
+            .map(|entry| (entry.target, entry.cast))
+            .collect();
+
+        let info = UserdataInfo {
+            name: U::name(),
+            tag: U::tag(),
+            kind: UserdataKind::of::<U>(),
+            parent: parent.as_ref().map(|(name, _, _, _)| *name),
+            methods: methods.iter().map(|(name, _)| *name).collect(),
+        };
 
         extern "C-unwind" fn dtor<U: Userdata>(_: *mut sys::lua_State, ud: *mut ffi::c_void) {
             let ud = ud.cast::<RefCell<U>>();
             unsafe { ud.drop_in_place() };
         }
 
+        extern "C-unwind" fn index<C: Config, U: Userdata>(ctx: Context<C>) -> FnReturn {
+            ctx.reserve(2);
+
+            if let Some(field) = ctx.to_string_str(2) {
+                let fields = ctx.to_light_userdata_unchecked::<Fields<C>>(sys::lua_upvalueindex(2));
+                let fields = unsafe { &*fields };
+
+                if fields.get(&*ctx.arg_userdata_ref::<U>(1), &ctx, field) {
+                    return ctx.ret_with(1);
+                }
+            }
+
+            ctx.push_upvalue(1);
+            ctx.push_copy(2);
+            ctx.table_get(-2);
+
+            if U::has_uservalue() && ctx.is_nil(-1) {
+                ctx.pop(1);
+                ctx.uservalue_get(1, 2);
+            }
+
+            ctx.ret_with(1)
+        }
+
+        extern "C-unwind" fn namecall<C: Config, U: Userdata>(ctx: Context<C>) -> FnReturn {
+            let mut atom = -1;
+            let name = unsafe { sys::lua_namecallatom(ctx.as_ptr(), &mut atom) };
+
+            let dispatch = ctx.to_light_userdata_unchecked::<Dispatch>(sys::lua_upvalueindex(1));
+            let dispatch = unsafe { &*dispatch };
+
+            if let Ok(atom) = usize::try_from(atom)
+                && let Some(Some(func)) = dispatch.get(atom)
+            {
+                return FnReturn(func(ctx.as_ptr()));
+            }
+
+            if name.is_null() {
+                ctx.error_msg("__namecall called without a method name");
+            }
+
+            let name = unsafe { ffi::CStr::from_ptr(name) };
+            ctx.reserve(2);
+
+            ctx.push_upvalue(2);
+            ctx.push_string(name.to_bytes());
+            ctx.table_get(-2);
+            ctx.remove(-2);
+
+            if U::has_uservalue() && ctx.is_nil(-1) {
+                ctx.pop(1);
+                ctx.push_string(name.to_bytes());
+                ctx.uservalue_get(1, -1);
+                ctx.remove(-2);
+            }
+
+            if !ctx.is_function(-1) {
+                let name = name.to_string_lossy();
+                ctx.error_msg(format!("{} has no method '{name}'", U::name()));
+            }
+
+            let nargs = ctx.get_top() - 1;
+            ctx.insert(1);
+
+            unsafe { sys::lua_call(ctx.as_ptr(), nargs, sys::LUA_MULTRET) };
+
+            let nresults = ctx.get_top();
+            ctx.ret_with(nresults as _)
+        }
+
+        extern "C-unwind" fn newindex<C: Config, U: Userdata>(ctx: Context<C>) -> FnReturn {
+            let field = if U::has_uservalue() {
+                ctx.to_string_str(2)
+            } else {
+                Some(ctx.arg_string_str(2))
+            };
+
+            let fields = ctx.to_light_userdata_unchecked::<Fields<C>>(sys::lua_upvalueindex(1));
+            let fields = unsafe { &*fields };
+
+            let found = match field {
+                Some(field) => fields.set(&mut *ctx.arg_userdata_mut::<U>(1), &ctx, field, 3),
+                None => false,
+            };
+
+            if found {
+                return ctx.ret();
+            }
+
+            if !U::has_uservalue() {
+                let field = field.unwrap_or_default();
+                ctx.error_msg(format!("{} has no field '{field}'", U::name()));
+            }
+
+            ctx.arg_userdata::<U>(1);
+            ctx.push_userdata_uservalue(1);
+            ctx.push_copy(2);
+            ctx.push_copy(3);
+            ctx.table_set_raw(-3);
+
+            ctx.ret()
+        }
+
         let stack = self.stack();
+        stack.reserve(4);
         stack.push_table();
 
         stack.push_string(U::name());
@@ -136,14 +772,107 @@ impl<C: Config> State<C> {
             stack.table_set_raw(-3);
         }
 
-        stack.push_copy(-1);
+        if let Some((_, methods, _, _)) = parent {
+            stack.push_table();
+            unsafe { sys::lua_getref(self.as_ptr(), methods) };
+            stack.table_set_raw_field(-2, c"__index");
+            unsafe { sys::lua_setmetatable(self.as_ptr(), -2) };
+        }
+
+        let methods = unsafe { sys::lua_ref(self.as_ptr(), -1) };
+
+        if let Some(dispatch) = &dispatch {
+            stack.push_light_userdata(&**dispatch as *const Dispatch as *mut Dispatch);
+            stack.push_copy(-2);
+            stack.push_extern_closure(c"__namecall", 2, namecall::<C, U>);
+        } else {
+            stack.push_copy(-1);
+        }
+
         stack.table_set_raw_field(-3, c"__namecall");
-        stack.table_set_raw_field(-2, c"__index");
 
-        unsafe {
-            sys::lua_setuserdatametatable(self.as_ptr(), U::tag() as _);
-            sys::lua_setuserdatadtor(self.as_ptr(), U::tag() as _, Some(dtor::<U>));
+        if fields.empty && !U::has_uservalue() {
+            stack.table_set_raw_field(-2, c"__index");
+        } else {
+            let ptr = Rc::as_ptr(&fields) as *mut Fields<C>;
+
+            stack.push_light_userdata(ptr);
+            stack.push_extern_closure(c"__index", 2, index::<C, U>);
+            stack.table_set_raw_field(-2, c"__index");
+
+            stack.push_light_userdata(ptr);
+            stack.push_extern_closure(c"__newindex", 1, newindex::<C, U>);
+            stack.table_set_raw_field(-2, c"__newindex");
         }
+
+        let untagged = match info.kind {
+            UserdataKind::Tagged => unsafe {
+                sys::lua_setuserdatametatable(self.as_ptr(), U::tag() as _);
+                sys::lua_setuserdatadtor(self.as_ptr(), U::tag() as _, Some(dtor::<U>));
+
+                None
+            },
+            UserdataKind::Untagged => {
+                let ptr = unsafe { sys::lua_topointer(self.as_ptr(), -1) };
+                shared.untagged.borrow_mut().insert(ptr, U::tag());
+
+                let untagged = Untagged {
+                    metatable: unsafe { sys::lua_ref(self.as_ptr(), -1) },
+                };
+
+                stack.pop(1);
+                Some(untagged)
+            }
+        };
+
+        let registered = Registered {
+            info,
+            methods,
+            casts,
+            dispatch,
+            fields: NonNull::new(Rc::into_raw(fields) as *mut ()).unwrap(),
+            drop_fields: Fields::<C>::drop_raw,
+            untagged,
+        };
+
+        shared.userdata.borrow_mut().insert(U::tag(), registered);
+        Ok(())
+    }
+
+    // Names the tag so that `typeof` reports it.
+    pub fn open_light_userdata<T: LightUserdata>(&self) -> Result<(), RegisterError> {
+        let tag = T::tag() as _;
+
+        if !unsafe { sys::lua_getlightuserdataname(self.as_ptr(), tag) }.is_null() {
+            return Err(RegisterError::AlreadyRegistered(T::name()));
+        }
+
+        let name = ffi::CString::new(T::name()).expect("light userdata name contains null byte");
+        unsafe { sys::lua_setlightuserdataname(self.as_ptr(), tag, name.as_ptr()) };
+
+        Ok(())
+    }
+
+    pub fn is_registered<U: Userdata>(&self) -> bool {
+        self.stack().is_registered::<U>()
+    }
+
+    pub fn userdata_kind<U: Userdata>(&self) -> Option<UserdataKind> {
+        self.is_registered::<U>().then(UserdataKind::of::<U>)
+    }
+
+    pub fn registered_userdata(&self) -> Vec<UserdataInfo> {
+        let shared = unsafe { self.shared.as_ref() };
+
+        let mut registered = shared
+            .userdata
+            .borrow()
+            .values()
+            .map(|registered| registered.info.clone())
+            .collect::<Vec<_>>();
+
+        registered.sort_by_key(|info| info.tag);
+        registered
     }
 
     pub fn open_std(&self) {
@@ -196,6 +925,159 @@ impl<C: Config> State<C> {
 
     pub fn sandbox(&self) {
         unsafe { sys::luaL_sandbox(self.as_ptr()) }
+        unsafe { self.shared.as_ref() }.safeenv.set(true);
+    }
+
+    // Records the current globals so that `rollback` can return to them. This
+    // is meant to be taken once the state is prepared and sandboxed, since the
+    // snapshot is shallow and tables inside of it aren't copied.
+    // Fails if a table reachable from the globals, through their values and
+    // metatables, isn't read-only, since `rollback` only restores the globals
+    // table itself. Freezing libraries and calling `sandbox` first avoids
+    // that. Tables used as keys, or held in the upvalues of functions, aren't
+    // checked.
+    pub fn checkpoint(&mut self) -> Result<(), CheckpointError> {
+        self.drop_checkpoint();
+
+        let stack = self.stack();
+        stack.reserve(4);
+
+        let mut seen = HashSet::new();
+        seen.insert(unsafe { sys::lua_topointer(self.as_ptr(), sys::LUA_GLOBALSINDEX) });
+
+        if let Some(path) = find_mutable(stack, sys::LUA_GLOBALSINDEX, "_G", &mut seen) {
+            return Err(CheckpointError::MutableTable(path));
+        }
+
+        stack.clone_table(sys::LUA_GLOBALSINDEX);
+        let globals = unsafe { sys::lua_ref(self.as_ptr(), -1) };
+        stack.pop(1);
+
+        stack.push_table();
+        let registry = stack.get_top();
+
+        stack.iter(sys::LUA_REGISTRYINDEX, || {
+            if is_user_key(stack, -2) {
+                stack.push_copy(-2);
+                stack.push_copy(-2);
+                stack.table_set_raw(registry);
+            }
+
+            ControlFlow::<()>::Continue(())
+        });
+
+        let registry = unsafe { sys::lua_ref(self.as_ptr(), -1) };
+        stack.pop(1);
+
+        let libraries = unsafe { self.libraries.as_ref() };
+        let libraries = libraries
+            .borrow()
+            .iter()
+            .map(|entry| (entry.name, entry.loaded))
+            .collect();
+
+        self.checkpoint = Some(Checkpoint {
+            globals,
+            registry,
+            readonly: stack.table_get_readonly(sys::LUA_GLOBALSINDEX),
+            safeenv: unsafe { self.shared.as_ref() }.safeenv.get(),
+            ref_serial: unsafe { self.shared.as_ref() }.ref_serial.get(),
+            libraries,
+        });
+
+        Ok(())
+    }
+
+    // Restores the globals, the string keys of the registry and the open
+    // libraries to the last `checkpoint`. Userdata registered since then
+    // stays registered, and so do lu's own `lu.*` registry keys. Registry
+    // entries are restored by reference, so tables such as `_LOADED` get
+    // back their old identity but keep any changes made to their contents.
+    // `Ref`s created since the checkpoint must be dropped first.
+    pub fn rollback(&mut self) -> Result<(), CheckpointError> {
+        let Some(checkpoint) = &self.checkpoint else {
+            return Err(CheckpointError::NoCheckpoint);
+        };
+
+        let live = unsafe { self.shared.as_ref() }
+            .refs
+            .borrow()
+            .iter()
+            .filter(|serial| **serial >= checkpoint.ref_serial)
+            .count();
+
+        if live > 0 {
+            return Err(CheckpointError::LiveRefs(live));
+        }
+
+        let stack = self.stack();
+        stack.reserve(4);
+
+        unsafe { sys::lua_getref(self.as_ptr(), checkpoint.registry) };
+        let registry = stack.get_top();
+
+        stack.iter(sys::LUA_REGISTRYINDEX, || {
+            if is_user_key(stack, -2) {
+                stack.push_copy(-2);
+                stack.table_get_raw(registry);
+
+                if stack.type_of(-1) == Type::Nil {
+                    stack.push_copy(-3);
+                    stack.push_nil();
+                    stack.table_set_raw(sys::LUA_REGISTRYINDEX);
+                }
+
+                stack.pop(1);
+            }
+
+            ControlFlow::<()>::Continue(())
+        });
+
+        restore(stack, registry, sys::LUA_REGISTRYINDEX);
+        stack.pop(1);
+
+        stack.table_set_readonly(sys::LUA_GLOBALSINDEX, false);
+        stack.clear_table(sys::LUA_GLOBALSINDEX);
+
+        unsafe { sys::lua_getref(self.as_ptr(), checkpoint.globals) };
+        restore(stack, stack.get_top(), sys::LUA_GLOBALSINDEX);
+        stack.pop(1);
+
+        stack.table_set_readonly(sys::LUA_GLOBALSINDEX, checkpoint.readonly);
+        stack.set_safeenv(sys::LUA_GLOBALSINDEX, checkpoint.safeenv);
+        unsafe { self.shared.as_ref() }
+            .safeenv
+            .set(checkpoint.safeenv);
+
+        let libraries = unsafe { self.libraries.as_ref() };
+        let mut libraries = libraries.borrow_mut();
+
+        libraries.retain_mut(|entry| {
+            let loaded = checkpoint
+                .libraries
+                .iter()
+                .find(|(name, _)| *name == entry.name)
+                .map(|(_, loaded)| *loaded);
+
+            match loaded {
+                Some(loaded) => {
+                    entry.loaded = loaded;
+                    true
+                }
+                None => false,
+            }
+        });
+
+        Ok(())
+    }
+
+    fn drop_checkpoint(&mut self) {
+        if let Some(checkpoint) = self.checkpoint.take() {
+            unsafe {
+                sys::lua_unref(self.as_ptr(), checkpoint.globals);
+                sys::lua_unref(self.as_ptr(), checkpoint.registry);
+            }
+        }
     }
 
     pub fn new_thread(&self) -> ThreadRef<C> {
@@ -205,3 +1087,127 @@ impl<C: Config> State<C> {
         thread
     }
 }
+
+// Returns the path to the first table reachable from the table at `idx` that
+// isn't read-only, skipping the tables in `seen`.
+fn find_mutable<C: Config>(
+    stack: &Stack<C>,
+    idx: i32,
+    path: &str,
+    seen: &mut HashSet<*const ffi::c_void>,
+) -> Option<String> {
+    stack.reserve(3);
+
+    if unsafe { sys::lua_getmetatable(stack.as_ptr(), idx) } != 0 {
+        let found = check_table(stack, &format!("getmetatable({path})"), seen);
+        stack.pop(1);
+
+        if found.is_some() {
+            return found;
+        }
+    }
+
+    stack.iter(idx, || {
+        if stack.type_of(-1) != Type::Table {
+            return ControlFlow::Continue(());
+        }
+
+        let path = match stack.type_of(-2) {
+            Type::String => format!("{path}.{}", stack.to_string_str(-2).unwrap_or("?")),
+            Type::Number => format!("{path}[{}]", stack.to_number(-2).unwrap_or_default()),
+            ty => format!("{path}[{ty:?}]"),
+        };
+
+        match check_table(stack, &path, seen) {
+            Some(found) => ControlFlow::Break(found),
+            None => ControlFlow::Continue(()),
+        }
+    })
+}
+
+// Checks the table on top of the stack and everything reachable from it.
+fn check_table<C: Config>(
+    stack: &Stack<C>,
+    path: &str,
+    seen: &mut HashSet<*const ffi::c_void>,
+) -> Option<String> {
+    if !seen.insert(unsafe { sys::lua_topointer(stack.as_ptr(), -1) }) {
+        return None;
+    }
+
+    if !stack.table_get_readonly(-1) {
+        return Some(path.to_owned());
+    }
+
+    find_mutable(stack, stack.get_top(), path, seen)
+}
+
+// String keys of the registry other than lu's own, which hold state that
+// lives alongside the VM rather than in its globals.
+fn is_user_key<C: Config>(stack: &Stack<C>, idx: i32) -> bool {
+    stack.type_of(idx) == Type::String
+        && !stack
+            .to_string_str(idx)
+            .is_some_and(|key| key.starts_with("lu."))
+}
+
+// Copies every entry of the table at `from` into the table at `to`.
+fn restore<C: Config>(stack: &Stack<C>, from: i32, to: i32) {
+    stack.iter(from, || {
+        stack.push_copy(-2);
+        stack.push_copy(-2);
+        stack.table_set_raw(to);
+
+        ControlFlow::<()>::Continue(())
+    });
+}
+
+// Pushes a library that's about to be set in the globals table at `globals`.
+// Once sandboxed, imports of the globals are resolved when scripts load, so
+// the library is frozen to keep them valid, and if it still holds a mutable
+// table, safeenv is turned off.
+fn push_library<C: Config>(stack: &Stack<C>, globals: i32, library: &Library<C>) {
+    let sandboxed = stack.table_get_readonly(globals);
+    library.push_frozen(stack, sandboxed);
+
+    if sandboxed && check_table(stack, "", &mut HashSet::new()).is_some() {
+        stack.set_safeenv(globals, false);
+        unsafe { Shared::get(stack.as_ptr()) }.safeenv.set(false);
+    }
+}
+
+// Sets a global in the globals table at `globals` from the key and value on
+// top of the stack, even once `sandbox` has made the globals read-only.
+fn set_global<C: Config>(stack: &Stack<C>, globals: i32) {
+    let sandboxed = stack.table_get_readonly(globals);
+
+    if sandboxed {
+        stack.table_set_readonly(globals, false);
+    }
+
+    stack.table_set_raw(globals);
+
+    if sandboxed {
+        stack.table_set_readonly(globals, true);
+    }
+}
+
+// A `State` that's only reachable through its owner can be moved to another
+// OS thread as long as all of the host data it owns can be. `ThreadMain`
+// handles and userdata aren't tracked, so `State::into_send` leaves that to
+// its caller.
+pub struct SendableState<C: Config>(State<C>);
+
+unsafe impl<C: Config> Send for SendableState<C>
+where
+    C::MainData: Send,
+    C::ThreadData: Send,
+    C::Allocator: Send,
+{
+}
+
+impl<C: Config> SendableState<C> {
+    pub fn into_inner(self) -> State<C> {
+        self.0
+    }
+}
@@ -1,6 +1,6 @@
 use std::{cell::RefCell, marker::PhantomData, ops::Deref, ptr::NonNull};
 
-use crate::{Config, Ref, Stack, Status};
+use crate::{Config, Environment, Ref, Stack, Status};
 
 #[repr(transparent)]
 pub struct Thread<C: Config>(
@@ -43,6 +43,15 @@ impl<C: Config> Thread<C> {
         unsafe { sys::luaL_sandboxthread(self.as_ptr()) }
     }
 
+    // Makes `env` the thread's globals. Sandboxing the thread afterwards
+    // gives it a proxy of `env`, so its own writes stay out of `env` too.
+    pub fn set_env(&self, env: &Environment<C>) {
+        let stack = self.stack();
+
+        env.push(stack);
+        stack.replace(sys::LUA_GLOBALSINDEX);
+    }
+
     pub fn resume(&self, from: Option<&Thread<C>>, nargs: u32) -> Status {
         let from = match from {
             Some(thread) => thread.as_ptr(),
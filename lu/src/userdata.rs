@@ -1,35 +1,363 @@
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::{
+    any::{Any, TypeId},
+    cell::{BorrowError, BorrowMutError, Ref, RefCell, RefMut},
+    collections::HashMap,
+    ffi::c_void,
+    fmt,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicU32, Ordering},
+    },
+};
 
-use crate::{Config, Context, FnReturn, Function, Status};
+use crate::{
+    Config, Context, FnReturn, Function, Stack, Status, TypedFunction, extra::luau_method,
+};
 
 pub fn unique_tag() -> u32 {
     static COUNT: AtomicU32 = AtomicU32::new(1);
 
+    COUNT.fetch_add(1, Ordering::Relaxed)
+}
+
+// Light userdata can't carry a metatable, so unlike full userdata there is no
+// fallback once the tags run out.
+pub fn unique_light_tag() -> u32 {
+    static COUNT: AtomicU32 = AtomicU32::new(1);
+
     let tag = COUNT.fetch_add(1, Ordering::Relaxed);
     assert!(
-        tag < sys::LUA_UTAG_LIMIT as u32,
-        "userdata tag limit exceeded",
+        tag < sys::LUA_LUTAG_LIMIT as u32,
+        "light userdata tag limit exceeded",
     );
 
     tag
 }
 
-pub trait Userdata {
+pub trait LightUserdata: 'static {
     fn tag() -> u32;
     fn name() -> &'static str;
 }
 
-#[derive(Default)]
+// Luau only has `LUA_UTAG_LIMIT` userdata tags, so the types given tags past
+// that are pushed as untagged userdata with a destructor and are identified
+// by their metatable instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserdataKind {
+    Tagged,
+    Untagged,
+}
+
+impl UserdataKind {
+    pub fn of<U: Userdata>() -> Self {
+        if U::tag() < sys::LUA_UTAG_LIMIT as u32 {
+            UserdataKind::Tagged
+        } else {
+            UserdataKind::Untagged
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserdataInfo {
+    pub name: &'static str,
+    pub tag: u32,
+    pub kind: UserdataKind,
+    pub parent: Option<&'static str>,
+    pub methods: Vec<&'static str>,
+}
+
+// Returned by `State::open_userdata` and `State::open_light_userdata`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegisterError {
+    AlreadyRegistered(&'static str),
+    ParentNotRegistered {
+        name: &'static str,
+        parent: &'static str,
+    },
+    MethodsForOtherType {
+        name: &'static str,
+        source: &'static str,
+    },
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegisterError::AlreadyRegistered(name) => {
+                write!(f, "userdata type {name} is already registered")
+            }
+            RegisterError::ParentNotRegistered { name, parent } => {
+                write!(
+                    f,
+                    "parent {parent} of userdata type {name} is not registered"
+                )
+            }
+            RegisterError::MethodsForOtherType { name, source } => {
+                write!(
+                    f,
+                    "methods for userdata type {source} were opened as {name}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for RegisterError {}
+
+// Returned by `Stack::try_push_userdata`, handing the value back.
+pub struct UnregisteredUserdata<T>(pub T);
+
+impl<T> UnregisteredUserdata<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: Userdata> fmt::Debug for UnregisteredUserdata<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("UnregisteredUserdata")
+            .field(&T::name())
+            .finish()
+    }
+}
+
+impl<T: Userdata> fmt::Display for UnregisteredUserdata<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "userdata type {} is not registered", T::name())
+    }
+}
+
+impl<T: Userdata> std::error::Error for UnregisteredUserdata<T> {}
+
+// Generic userdata types can't use a `static` per type, so their tags are
+// handed out per `TypeId` instead. Each thread caches the tags it has seen,
+// so the lock is only taken the first time a thread asks for a type.
+pub fn unique_tag_for<T: 'static>() -> u32 {
+    static TAGS: OnceLock<Mutex<HashMap<TypeId, u32>>> = OnceLock::new();
+
+    thread_local! {
+        static CACHE: RefCell<HashMap<TypeId, u32>> = RefCell::default();
+    }
+
+    let id = TypeId::of::<T>();
+
+    CACHE.with(|cache| {
+        if let Some(tag) = cache.borrow().get(&id) {
+            return *tag;
+        }
+
+        let tag = *TAGS
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap()
+            .entry(id)
+            .or_insert_with(unique_tag);
+
+        cache.borrow_mut().insert(id, tag);
+        tag
+    })
+}
+
+// Builds and caches a name like `Query<Position>` for a generic userdata
+// type, with the module paths stripped from the type arguments. Cached per
+// thread like `unique_tag_for`.
+pub fn generic_name_for<T: 'static>(
+    base: &str,
+    args: impl FnOnce() -> Vec<String>,
+) -> &'static str {
+    static NAMES: OnceLock<Mutex<HashMap<TypeId, &'static str>>> = OnceLock::new();
+
+    thread_local! {
+        static CACHE: RefCell<HashMap<TypeId, &'static str>> = RefCell::default();
+    }
+
+    let id = TypeId::of::<T>();
+
+    CACHE.with(|cache| {
+        if let Some(name) = cache.borrow().get(&id) {
+            return *name;
+        }
+
+        let name = *NAMES
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap()
+            .entry(id)
+            .or_insert_with(|| {
+                let args = args()
+                    .iter()
+                    .map(|arg| short_type_name(arg))
+                    .collect::<Vec<_>>();
+
+                format!("{base}<{}>", args.join(", ")).leak()
+            });
+
+        cache.borrow_mut().insert(id, name);
+        name
+    })
+}
+
+pub(crate) fn short_type_name(name: &str) -> String {
+    let mut short = String::with_capacity(name.len());
+    let mut segment = 0;
+    let mut chars = name.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == ':' && chars.peek() == Some(&':') {
+            chars.next();
+            short.truncate(segment);
+        } else {
+            short.push(c);
+
+            if !(c.is_alphanumeric() || c == '_') {
+                segment = short.len();
+            }
+        }
+    }
+
+    short
+}
+
+pub trait Userdata: 'static {
+    fn tag() -> u32;
+    fn name() -> &'static str;
+
+    // Fields are resolved by `__index` and `__newindex` before falling back
+    // to the methods table.
+    fn fields() -> &'static [&'static str] {
+        &[]
+    }
+
+    // The Luau type of a field, used by `Methods::type_definitions`.
+    fn field_type(field: &str) -> Option<String> {
+        let _ = field;
+        None
+    }
+
+    // Instances get a table for values set from scripts that aren't native
+    // fields, which `__index` consults after fields and methods.
+    fn has_uservalue() -> bool {
+        false
+    }
+
+    // Pushes the value of the field and returns true, or returns false
+    // without pushing anything if there is no such field.
+    fn get_field<C: Config>(&self, stack: &Stack<C>, field: &str) -> bool {
+        let _ = (stack, field);
+        false
+    }
+
+    // Sets the field from argument `narg` and returns true, or returns false
+    // if there is no such field.
+    fn set_field<C: Config>(&mut self, ctx: &Context<C>, field: &str, narg: u32) -> bool {
+        let _ = (ctx, field, narg);
+        false
+    }
+}
+
+// Borrows a userdata value as another type, either a parent it extends or a
+// trait object it implements. The argument points at the `RefCell` of the
+// concrete type that registered the cast.
+type BorrowFn<T> = dyn for<'a> Fn(&'a c_void) -> Result<Ref<'a, T>, BorrowError>;
+type BorrowMutFn<T> = dyn for<'a> Fn(&'a c_void) -> Result<RefMut<'a, T>, BorrowMutError>;
+
+pub(crate) struct Cast<T: ?Sized> {
+    pub(crate) borrow: Box<BorrowFn<T>>,
+    pub(crate) borrow_mut: Box<BorrowMutFn<T>>,
+}
+
+// Maps a value of a userdata type to the parent it extends, so that the
+// parent's fields can be resolved on it.
+type UpcastFn = dyn for<'a> Fn(&'a dyn Any) -> &'a dyn Any;
+type UpcastMutFn = dyn for<'a> Fn(&'a mut dyn Any) -> &'a mut dyn Any;
+
+pub(crate) struct Upcast {
+    pub(crate) as_ref: Box<UpcastFn>,
+    pub(crate) as_mut: Box<UpcastMutFn>,
+}
+
+pub(crate) struct CastEntry {
+    pub(crate) source: (u32, &'static str),
+    pub(crate) target: TypeId,
+    pub(crate) cast: Box<dyn Any>,
+}
+
 pub struct Methods<C: Config> {
     pub(crate) methods: Vec<(&'static str, Function<C>)>,
+    pub(crate) signatures: HashMap<&'static str, String>,
+    pub(crate) parent: Option<(u32, &'static str)>,
+    pub(crate) upcast: Option<Upcast>,
+    pub(crate) casts: Vec<CastEntry>,
+}
+
+impl<C: Config> Default for Methods<C> {
+    fn default() -> Self {
+        Self {
+            methods: Vec::new(),
+            signatures: HashMap::new(),
+            parent: None,
+            upcast: None,
+            casts: Vec::new(),
+        }
+    }
 }
 
 impl<C: Config> Methods<C> {
+    // Methods and fields missing from `U` are looked up on `P`, and `U` is
+    // accepted wherever `P` is borrowed. Casts aren't transitive, so
+    // grandparents need their own `implements`.
+    pub fn extends<U, P>(mut self, as_ref: fn(&U) -> &P, as_mut: fn(&mut U) -> &mut P) -> Self
+    where
+        U: Userdata,
+        P: Userdata,
+    {
+        self.parent = Some((P::tag(), P::name()));
+        self.upcast = Some(Upcast {
+            as_ref: Box::new(move |ud| as_ref(ud.downcast_ref::<U>().unwrap())),
+            as_mut: Box::new(move |ud| as_mut(ud.downcast_mut::<U>().unwrap())),
+        });
+
+        self.implements(as_ref, as_mut)
+    }
+
+    pub fn implements<U, T>(mut self, as_ref: fn(&U) -> &T, as_mut: fn(&mut U) -> &mut T) -> Self
+    where
+        U: Userdata,
+        T: ?Sized + 'static,
+    {
+        let cast = Cast::<T> {
+            borrow: Box::new(move |ud| {
+                let ud = unsafe { &*(ud as *const c_void).cast::<RefCell<U>>() };
+                ud.try_borrow().map(|ud| Ref::map(ud, as_ref))
+            }),
+            borrow_mut: Box::new(move |ud| {
+                let ud = unsafe { &*(ud as *const c_void).cast::<RefCell<U>>() };
+                ud.try_borrow_mut().map(|ud| RefMut::map(ud, as_mut))
+            }),
+        };
+
+        self.casts.push(CastEntry {
+            source: (U::tag(), U::name()),
+            target: TypeId::of::<T>(),
+            cast: Box::new(cast),
+        });
+
+        self
+    }
+
     pub fn with_method(mut self, name: &'static str, func: Function<C>) -> Self {
         self.methods.push((name, func));
         self
     }
 
+    // Like `with_method`, but the signature is used for type definitions.
+    pub fn with_typed_method(mut self, name: &'static str, func: TypedFunction<C>) -> Self {
+        let (func, signature) = func.into_parts();
+        self.signatures.insert(name, signature);
+        self.with_method(name, func)
+    }
+
     pub fn with_method_norm(
         self,
         name: &'static str,
@@ -46,4 +374,26 @@ impl<C: Config> Methods<C> {
     ) -> Self {
         self.with_method(name, Function::cont(name, func, cont))
     }
+
+    // Emits a luau-lsp class declaration for `U`. Fields without a known type
+    // are declared as `any`.
+    pub fn type_definitions<U: Userdata>(&self) -> String {
+        let mut defs = match self.parent {
+            Some((_, parent)) => format!("declare class {} extends {parent}\n", U::name()),
+            None => format!("declare class {}\n", U::name()),
+        };
+
+        for field in U::fields() {
+            let ty = U::field_type(field).unwrap_or_else(|| "any".into());
+            defs.push_str(&format!("    {field}: {ty}\n"));
+        }
+
+        for (name, _) in &self.methods {
+            let signature = self.signatures.get(name).map(String::as_str);
+            defs.push_str(&format!("    {}\n", luau_method(name, signature)));
+        }
+
+        defs.push_str("end\n");
+        defs
+    }
 }
@@ -0,0 +1,262 @@
+use std::fmt::Display;
+
+use crate::{Config, Context, Stack, Type};
+
+pub trait FromLuau: Sized {
+    fn from_luau<C: Config>(stack: &Stack<C>, idx: i32) -> Option<Self>;
+    fn luau_type() -> String;
+}
+
+pub trait IntoLuau {
+    fn push<C: Config>(self, stack: &Stack<C>);
+    fn luau_type() -> String;
+}
+
+// Converts the value returned from a host function into values on the stack,
+// raising an error for `Err`.
+pub trait Returns {
+    fn push_returns<C: Config>(self, ctx: &Context<C>) -> u32;
+    fn returns_type() -> String;
+}
+
+// Returns each element of a tuple as a separate value, since the tuples
+// themselves may already convert to a single value (such as vectors).
+pub struct Multi<T>(pub T);
+
+impl FromLuau for bool {
+    fn from_luau<C: Config>(stack: &Stack<C>, idx: i32) -> Option<Self> {
+        stack.to_boolean(idx)
+    }
+
+    fn luau_type() -> String {
+        "boolean".into()
+    }
+}
+
+impl IntoLuau for bool {
+    fn push<C: Config>(self, stack: &Stack<C>) {
+        stack.push_boolean(self);
+    }
+
+    fn luau_type() -> String {
+        "boolean".into()
+    }
+}
+
+macro_rules! float {
+    ($($ty:ty),*) => {$(
+        impl FromLuau for $ty {
+            fn from_luau<C: Config>(stack: &Stack<C>, idx: i32) -> Option<Self> {
+                stack.to_number(idx).map(|n| n as _)
+            }
+
+            fn luau_type() -> String {
+                "number".into()
+            }
+        }
+
+        impl IntoLuau for $ty {
+            fn push<C: Config>(self, stack: &Stack<C>) {
+                stack.push_number(self as _);
+            }
+
+            fn luau_type() -> String {
+                "number".into()
+            }
+        }
+    )*};
+}
+
+float!(f32, f64);
+
+// Integers only accept numbers that are whole and in range, rather than
+// silently truncating.
+macro_rules! integer {
+    ($($ty:ty),*) => {$(
+        impl FromLuau for $ty {
+            fn from_luau<C: Config>(stack: &Stack<C>, idx: i32) -> Option<Self> {
+                let n = stack.to_number(idx)?;
+
+                // `MAX` rounds up to a power of two for the 64-bit types, so
+                // the bound is that power of two, and excluded.
+                let end = (<$ty>::MAX / 2 + 1) as f64 * 2.0;
+
+                if n.fract() == 0.0 && n >= <$ty>::MIN as f64 && n < end {
+                    Some(n as _)
+                } else {
+                    None
+                }
+            }
+
+            fn luau_type() -> String {
+                "number".into()
+            }
+        }
+
+        impl IntoLuau for $ty {
+            fn push<C: Config>(self, stack: &Stack<C>) {
+                stack.push_number(self as _);
+            }
+
+            fn luau_type() -> String {
+                "number".into()
+            }
+        }
+    )*};
+}
+
+integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl FromLuau for (f32, f32, f32) {
+    fn from_luau<C: Config>(stack: &Stack<C>, idx: i32) -> Option<Self> {
+        stack.to_vector(idx)
+    }
+
+    fn luau_type() -> String {
+        "vector".into()
+    }
+}
+
+impl IntoLuau for (f32, f32, f32) {
+    fn push<C: Config>(self, stack: &Stack<C>) {
+        stack.push_vector(self);
+    }
+
+    fn luau_type() -> String {
+        "vector".into()
+    }
+}
+
+impl FromLuau for String {
+    fn from_luau<C: Config>(stack: &Stack<C>, idx: i32) -> Option<Self> {
+        if stack.type_of(idx) == Type::String {
+            stack.to_string_str(idx).map(str::to_owned)
+        } else {
+            None
+        }
+    }
+
+    fn luau_type() -> String {
+        "string".into()
+    }
+}
+
+impl IntoLuau for String {
+    fn push<C: Config>(self, stack: &Stack<C>) {
+        stack.push_string(self);
+    }
+
+    fn luau_type() -> String {
+        "string".into()
+    }
+}
+
+impl IntoLuau for &str {
+    fn push<C: Config>(self, stack: &Stack<C>) {
+        stack.push_string(self);
+    }
+
+    fn luau_type() -> String {
+        "string".into()
+    }
+}
+
+impl<T: FromLuau> FromLuau for Option<T> {
+    fn from_luau<C: Config>(stack: &Stack<C>, idx: i32) -> Option<Self> {
+        match stack.type_of(idx) {
+            Type::None | Type::Nil => Some(None),
+            _ => T::from_luau(stack, idx).map(Some),
+        }
+    }
+
+    fn luau_type() -> String {
+        format!("{}?", T::luau_type())
+    }
+}
+
+impl<T: IntoLuau> IntoLuau for Option<T> {
+    fn push<C: Config>(self, stack: &Stack<C>) {
+        match self {
+            Some(value) => value.push(stack),
+            None => stack.push_nil(),
+        }
+    }
+
+    fn luau_type() -> String {
+        format!("{}?", T::luau_type())
+    }
+}
+
+impl Returns for () {
+    fn push_returns<C: Config>(self, _ctx: &Context<C>) -> u32 {
+        0
+    }
+
+    fn returns_type() -> String {
+        "()".into()
+    }
+}
+
+impl<T: IntoLuau> Returns for T {
+    fn push_returns<C: Config>(self, ctx: &Context<C>) -> u32 {
+        ctx.reserve(1);
+        self.push(ctx);
+        1
+    }
+
+    fn returns_type() -> String {
+        <T as IntoLuau>::luau_type()
+    }
+}
+
+macro_rules! multi {
+    ($(($($name:ident),+)),*) => {$(
+        impl<$($name: IntoLuau),+> Returns for Multi<($($name,)+)> {
+            #[allow(non_snake_case)]
+            fn push_returns<C: Config>(self, ctx: &Context<C>) -> u32 {
+                let ($($name,)+) = self.0;
+                let n = [$(stringify!($name)),+].len() as u32;
+
+                ctx.reserve(n as _);
+                $($name.push(ctx);)+
+                n
+            }
+
+            fn returns_type() -> String {
+                let types = [$($name::luau_type()),+];
+                format!("({})", types.join(", "))
+            }
+        }
+    )*};
+}
+
+multi!(
+    (A),
+    (A, B),
+    (A, B, D),
+    (A, B, D, E),
+    (A, B, D, E, F),
+    (A, B, D, E, F, G),
+    (A, B, D, E, F, G, H),
+    (A, B, D, E, F, G, H, I)
+);
+
+impl<T: Returns, E: Display> Returns for Result<T, E> {
+    fn push_returns<C: Config>(self, ctx: &Context<C>) -> u32 {
+        match self {
+            Ok(value) => value.push_returns(ctx),
+            Err(err) => {
+                let msg = err.to_string();
+                drop(err);
+
+                ctx.reserve(1);
+                ctx.push_string(msg);
+                ctx.error()
+            }
+        }
+    }
+
+    fn returns_type() -> String {
+        T::returns_type()
+    }
+}
@@ -0,0 +1,117 @@
+struct Config;
+
+impl lu::Config for Config {
+    type Allocator = lu::DefaultAllocator;
+    type MainData = ();
+    type ThreadData = ();
+}
+
+// Runs `source` with the json library open, returning the string it returns
+// or the error it raised.
+fn run(source: &str) -> Result<String, String> {
+    let mut state = lu::State::<Config>::new((), lu::DefaultAllocator);
+    state.open_std();
+    state.open_library("json", lu::libs::json::library());
+
+    let compiled = lu::Compiler::default().compile(source.as_bytes());
+    let thread = state.new_thread();
+    let stack = thread.stack();
+
+    stack.push_bytecode(c"test", compiled.bytecode())?;
+
+    let status = thread.resume(None, 0);
+    let result = stack.to_string_str(-1).unwrap_or_default().to_owned();
+
+    match status {
+        lu::Status::Ok => Ok(result),
+        _ => Err(result),
+    }
+}
+
+#[test]
+fn encode_array() {
+    assert_eq!(
+        run("return json.encode({ 1, 'two', true, json.null })"),
+        Ok(r#"[1,"two",true,null]"#.into())
+    );
+
+    assert_eq!(run("return json.encode({})"), Ok("[]".into()));
+}
+
+#[test]
+fn encode_object() {
+    assert_eq!(
+        run("return json.encode({ b = 1.5, a = { c = 'x' } }, { sort_keys = true })"),
+        Ok(r#"{"a":{"c":"x"},"b":1.5}"#.into())
+    );
+}
+
+#[test]
+fn encode_mixed_table_as_object() {
+    assert_eq!(
+        run("return json.encode({ 1, nil, 3, x = 1 }, { sort_keys = true })"),
+        Ok(r#"{"1":1,"3":3,"x":1}"#.into())
+    );
+
+    assert_eq!(
+        run("return json.encode({ 1, 2, [4] = 4 }, { sort_keys = true })"),
+        Ok(r#"{"1":1,"2":2,"4":4}"#.into())
+    );
+}
+
+#[test]
+fn encode_pretty() {
+    assert_eq!(
+        run("return json.encode({ a = { 1, 2 } }, { pretty = true })"),
+        Ok("{\n  \"a\": [\n    1,\n    2\n  ]\n}".into())
+    );
+}
+
+#[test]
+fn encode_errors() {
+    let err = run("local t = {} t.t = t return json.encode(t)").unwrap_err();
+    assert!(err.contains("cannot encode a table that contains itself at $.t"));
+
+    let err = run("return json.encode({ 0 / 0 })").unwrap_err();
+    assert!(err.contains("cannot encode NaN or infinity at $[1]"));
+
+    let err = run("return json.encode({ f = print })").unwrap_err();
+    assert!(err.contains("cannot encode a function at $.f"));
+
+    let err = run(r#"return json.encode({ ['a"b\0'] = print })"#).unwrap_err();
+    assert!(err.contains(r#"cannot encode a function at $["a\"b\u0000"]"#));
+
+    let err = run("return json.encode({ [1] = 1, ['1'] = 2, x = 3 })").unwrap_err();
+    assert!(err.contains(r#"cannot encode key "1" more than once at $"#));
+
+    let err = run("return json.encode({ [2^60] = 1, x = 2 })").unwrap_err();
+    assert!(err.contains("beyond 2^53"));
+
+    let err = run("return json.encode({ [1.5] = 1 })").unwrap_err();
+    assert!(err.contains("cannot encode non-integer key 1.5"));
+}
+
+#[test]
+fn decode() {
+    assert_eq!(
+        run(r#"
+            local value = json.decode('{"a": [1, 2.5, "\\u00e9"], "b": null, "c": false}')
+            assert(value.a[1] == 1 and value.a[2] == 2.5 and value.a[3] == "é")
+            assert(value.b == json.null and value.c == false)
+            return json.encode(value, { sort_keys = true })
+        "#),
+        Ok(r#"{"a":[1,2.5,"é"],"b":null,"c":false}"#.into())
+    );
+}
+
+#[test]
+fn decode_errors() {
+    let err = run(r#"return json.decode('{"a": 1,\n  }')"#).unwrap_err();
+    assert!(err.contains("unexpected character '}' at line 2, column 3"));
+
+    let err = run(r#"return json.decode('[1] 2')"#).unwrap_err();
+    assert!(err.contains("unexpected character '2' at line 1, column 5"));
+
+    let err = run(r#"return json.decode('"abc')"#).unwrap_err();
+    assert!(err.contains("unexpected end of input"));
+}
@@ -0,0 +1,160 @@
+#![cfg(feature = "serde")]
+
+use std::{collections::BTreeMap, fmt};
+
+use lu::serde::{Options, from_stack, from_stack_with, to_stack, to_stack_with};
+
+struct Config;
+
+impl lu::Config for Config {
+    type Allocator = lu::DefaultAllocator;
+    type MainData = ();
+    type ThreadData = ();
+}
+
+fn state() -> lu::State<Config> {
+    lu::State::new((), lu::DefaultAllocator)
+}
+
+#[test]
+fn round_trip() {
+    let state = state();
+    let stack = state.stack();
+
+    let value = vec![vec![1u32, 2], vec![], vec![3]];
+    to_stack(stack, &value).unwrap();
+    assert_eq!(from_stack::<_, Vec<Vec<u32>>>(stack, -1).unwrap(), value);
+
+    let value = BTreeMap::from([("a".to_owned(), 1.5), ("b".to_owned(), -2.0)]);
+    to_stack(stack, &value).unwrap();
+    assert_eq!(
+        from_stack::<_, BTreeMap<String, f64>>(stack, -1).unwrap(),
+        value
+    );
+
+    let value = (Some(true), "text".to_owned());
+    to_stack(stack, &value).unwrap();
+    assert_eq!(
+        from_stack::<_, (Option<bool>, String)>(stack, -1).unwrap(),
+        value
+    );
+
+    to_stack(stack, &None::<i32>).unwrap();
+    assert_eq!(from_stack::<_, Option<i32>>(stack, -1).unwrap(), None);
+
+    assert_eq!(stack.get_top(), 4);
+}
+
+#[test]
+fn vectors() {
+    let state = state();
+    let stack = state.stack();
+
+    to_stack(stack, &(1.0f32, 2.0f32, 3.0f32)).unwrap();
+    assert_eq!(stack.type_of(-1), lu::Type::Vector);
+    assert_eq!(
+        from_stack::<_, [f32; 3]>(stack, -1).unwrap(),
+        [1.0, 2.0, 3.0]
+    );
+
+    let options = Options::default().with_vectors_as_arrays(false);
+    to_stack_with(stack, &(1.0f32, 2.0f32, 3.0f32), &options).unwrap();
+    assert_eq!(stack.type_of(-1), lu::Type::Table);
+
+    let components = from_stack_with::<_, BTreeMap<String, f32>>(stack, -2, &options).unwrap();
+    assert_eq!(
+        components,
+        BTreeMap::from([("x".into(), 1.0), ("y".into(), 2.0), ("z".into(), 3.0)])
+    );
+}
+
+#[test]
+fn bytes() {
+    let state = state();
+    let stack = state.stack();
+
+    to_stack(stack, &Bytes(b"\xffbytes")).unwrap();
+    assert_eq!(stack.type_of(-1), lu::Type::Buffer);
+
+    let options = Options::default().with_buffers_as_bytes(false);
+    to_stack_with(stack, &Bytes(b"bytes"), &options).unwrap();
+    assert_eq!(stack.to_string_str(-1), Some("bytes"));
+}
+
+#[test]
+fn errors() {
+    let state = state();
+    let stack = state.stack();
+
+    stack.push_number(1.5);
+    assert!(from_stack::<_, u32>(stack, -1).is_err());
+
+    stack.push_string("text");
+    assert!(from_stack::<_, Vec<u32>>(stack, -1).is_err());
+
+    assert_eq!(stack.get_top(), 2);
+}
+
+#[test]
+fn ignored_fields() {
+    let state = state();
+    let stack = state.stack();
+
+    let value = BTreeMap::from([
+        (
+            "a".to_owned(),
+            BTreeMap::from([("w", 1.0), ("x", 2.0), ("y", 3.0)]),
+        ),
+        ("b".to_owned(), BTreeMap::from([("x", 4.0), ("z", 5.0)])),
+    ]);
+    to_stack(stack, &value).unwrap();
+
+    for options in [Options::default(), Options::default().with_sort_keys(true)] {
+        let points = from_stack_with::<_, BTreeMap<String, X>>(stack, -1, &options).unwrap();
+        assert_eq!(points["a"].0, 2.0);
+        assert_eq!(points["b"].0, 4.0);
+        assert_eq!(points.len(), 2);
+    }
+
+    assert_eq!(stack.get_top(), 1);
+}
+
+// Serializes as bytes rather than as a sequence of numbers.
+struct Bytes<'a>(&'a [u8]);
+
+impl serde::Serialize for Bytes<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+// Reads the entries of a table until it finds `x`, leaving the rest unread.
+struct X(f64);
+
+impl<'de> serde::Deserialize<'de> for X {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = X;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a table with an x field")
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<X, A::Error> {
+                while let Some(key) = map.next_key::<String>()? {
+                    if key == "x" {
+                        return map.next_value().map(X);
+                    }
+
+                    map.next_value::<serde::de::IgnoredAny>()?;
+                }
+
+                Err(serde::de::Error::missing_field("x"))
+            }
+        }
+
+        deserializer.deserialize_struct("X", &["x"], Visitor)
+    }
+}
@@ -0,0 +1,116 @@
+struct Config;
+
+impl lu::Config for Config {
+    type Allocator = lu::DefaultAllocator;
+    type MainData = ();
+    type ThreadData = ();
+}
+
+#[lu::library(name = "physics")]
+mod physics {
+    pub const GRAVITY: f64 = 9.8;
+
+    pub fn raycast(from: f64, to: Option<f64>) -> (f64, bool) {
+        (from + to.unwrap_or(0.0), true)
+    }
+
+    #[lu::function(name = "version")]
+    pub fn get_version() -> String {
+        "1.0".into()
+    }
+
+    #[lu::library(name = "Shapes")]
+    pub mod shapes {
+        pub fn sphere(radius: f64) -> f64 {
+            radius
+        }
+    }
+}
+
+#[test]
+fn library() {
+    let library = physics::library::<Config>()
+        .with_constant("non-ident", "x")
+        .with_function_norm("untyped", untyped);
+
+    assert_eq!(
+        library.type_definitions(physics::LIBRARY_NAME),
+        "\
+declare physics: {
+    GRAVITY: number,
+    raycast: (from: number, to: number?) -> (number, boolean),
+    version: () -> string,
+    Shapes: {
+        sphere: (radius: number) -> number,
+    },
+    [\"non-ident\"]: string,
+    untyped: (...any) -> ...any,
+}
+"
+    );
+}
+
+extern "C-unwind" fn untyped(ctx: lu::Context<Config>) -> lu::FnReturn {
+    ctx.ret()
+}
+
+#[derive(lu::Userdata)]
+struct Shape {
+    #[lu(field)]
+    area: f64,
+}
+
+#[derive(lu::Userdata)]
+#[lu(name = "Circle")]
+struct Circle {
+    shape: Shape,
+
+    #[lu(field)]
+    radius: f64,
+
+    #[lu(field, readonly, name = "label")]
+    name: Option<String>,
+}
+
+#[lu::methods]
+impl Circle {
+    fn scale(&mut self, by: f64) {
+        self.radius *= by;
+    }
+
+    fn overlaps(&self, other: &Circle, margin: Option<f64>) -> bool {
+        let margin = margin.unwrap_or(0.0);
+        self.radius + other.radius + margin > 0.0
+    }
+
+    #[lu(name = "describe")]
+    fn to_string(&self) -> Result<(String, usize), String> {
+        let name = self.name.clone().ok_or("unnamed")?;
+        Ok((name, self.shape.area as usize))
+    }
+
+    #[lu(skip)]
+    #[allow(dead_code)]
+    fn skipped(&self) {}
+}
+
+#[test]
+fn userdata() {
+    let methods = Circle::methods::<Config>()
+        .extends(|circle: &Circle| &circle.shape, |circle| &mut circle.shape)
+        .with_method_norm("untyped", untyped);
+
+    assert_eq!(
+        methods.type_definitions::<Circle>(),
+        "\
+declare class Circle extends Shape
+    radius: number
+    label: string?
+    function scale(self, by: number): ()
+    function overlaps(self, other: Circle, margin: number?): boolean
+    function describe(self): (string, number)
+    function untyped(self, ...any): ...any
+end
+"
+    );
+}
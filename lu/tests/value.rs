@@ -0,0 +1,40 @@
+use lu::FromLuau;
+
+struct Config;
+
+impl lu::Config for Config {
+    type Allocator = lu::DefaultAllocator;
+    type MainData = ();
+    type ThreadData = ();
+}
+
+// Converts `n` from a number on the stack.
+fn from_number<T: FromLuau>(n: f64) -> Option<T> {
+    let state = lu::State::<Config>::new((), lu::DefaultAllocator);
+    let stack = state.stack();
+
+    stack.push_number(n);
+    T::from_luau(stack, -1)
+}
+
+#[test]
+fn integer_bounds() {
+    assert_eq!(from_number::<u8>(255.0), Some(255));
+    assert_eq!(from_number::<u8>(256.0), None);
+    assert_eq!(from_number::<u8>(-1.0), None);
+    assert_eq!(from_number::<i8>(-128.0), Some(-128));
+    assert_eq!(from_number::<i8>(128.0), None);
+    assert_eq!(from_number::<i32>(1.5), None);
+
+    let two_63 = 2f64.powi(63);
+    assert_eq!(from_number::<i64>(-two_63), Some(i64::MIN));
+    assert_eq!(from_number::<i64>(two_63), None);
+    assert_eq!(from_number::<i64>(two_63 - 1024.0), Some(i64::MAX - 1023));
+
+    let two_64 = 2f64.powi(64);
+    assert_eq!(from_number::<u64>(two_64), None);
+    assert_eq!(from_number::<u64>(two_64 - 2048.0), Some(u64::MAX - 2047));
+
+    assert_eq!(from_number::<i64>(f64::INFINITY), None);
+    assert_eq!(from_number::<u64>(f64::NAN), None);
+}